@@ -0,0 +1,220 @@
+//! Command-line and config-file handling for the demo binary.
+//!
+//! `NorayConfig` is layered from, lowest to highest priority: the built-in
+//! defaults, a TOML file, `NORAY_*` environment variables and finally the
+//! command-line flags.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+
+use crate::network::NorayConfig;
+
+/// Config file read when `--config` isn't given. Missing is not an error.
+pub const DEFAULT_CONFIG_PATH: &str = "noray.toml";
+
+/// Environment variable naming the config file, below `--config` in priority.
+pub const CONFIG_PATH_ENV: &str = "NORAY_CONFIG";
+
+#[derive(Debug, Parser)]
+#[command(name = "bevy-noray", about = "Bevy multiplayer demo over noray")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// TOML file to load the noray settings from.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Address of the noray server.
+    #[arg(long, global = true, value_name = "HOST")]
+    pub noray_host: Option<String>,
+
+    /// noray TCP control port.
+    #[arg(long, global = true, value_name = "PORT")]
+    pub tcp_port: Option<u16>,
+
+    /// noray UDP registration port.
+    #[arg(long, global = true, value_name = "PORT")]
+    pub udp_port: Option<u16>,
+
+    /// Host without a window, renderer or local player. Only valid with
+    /// `host`, whose `--players` then counts joiners only.
+    #[arg(long, global = true)]
+    pub headless: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+pub enum Command {
+    /// Host a game and wait for the other players.
+    Host {
+        /// Players in the session, counting the host unless `--headless`.
+        #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..))]
+        players: u32,
+    },
+    /// Join the game hosted under the given OpenID.
+    Join { host_oid: String },
+}
+
+impl Cli {
+    /// Builds the noray settings from the config file, the process
+    /// environment and this command line.
+    pub fn noray_config(&self) -> Result<NorayConfig, ConfigError> {
+        let env = |name: &str| std::env::var(name).ok();
+        let path = self
+            .config
+            .clone()
+            .or_else(|| env(CONFIG_PATH_ENV).map(PathBuf::from));
+
+        let mut config = load_config(path.as_deref(), env)?;
+        self.apply(&mut config);
+        Ok(config)
+    }
+
+    /// Overrides `config` with whichever flags were passed.
+    pub fn apply(&self, config: &mut NorayConfig) {
+        if let Some(host) = &self.noray_host {
+            config.host.clone_from(host);
+        }
+        if let Some(port) = self.tcp_port {
+            config.tcp_port = port;
+        }
+        if let Some(port) = self.udp_port {
+            config.udp_port = port;
+        }
+    }
+}
+
+/// The optional fields of a config file or of the `NORAY_*` variables.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigOverrides {
+    pub host: Option<String>,
+    pub tcp_port: Option<u16>,
+    pub udp_port: Option<u16>,
+    pub direct_connect: Option<bool>,
+    pub punch_timeout_ms: Option<u64>,
+}
+
+impl ConfigOverrides {
+    pub fn from_toml(source: &str) -> Result<Self, ConfigError> {
+        toml::from_str(source).map_err(|e| ConfigError::Parse(e.to_string()))
+    }
+
+    /// Reads `NORAY_HOST`, `NORAY_TCP_PORT`, `NORAY_UDP_PORT`,
+    /// `NORAY_DIRECT_CONNECT` and `NORAY_PUNCH_TIMEOUT_MS` through `env`.
+    pub fn from_env(env: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        Ok(Self {
+            host: env("NORAY_HOST"),
+            tcp_port: parse_env(&env, "NORAY_TCP_PORT")?,
+            udp_port: parse_env(&env, "NORAY_UDP_PORT")?,
+            direct_connect: parse_env(&env, "NORAY_DIRECT_CONNECT")?,
+            punch_timeout_ms: parse_env(&env, "NORAY_PUNCH_TIMEOUT_MS")?,
+        })
+    }
+
+    pub fn apply(self, config: &mut NorayConfig) {
+        if let Some(host) = self.host {
+            config.host = host;
+        }
+        if let Some(port) = self.tcp_port {
+            config.tcp_port = port;
+        }
+        if let Some(port) = self.udp_port {
+            config.udp_port = port;
+        }
+        if let Some(direct_connect) = self.direct_connect {
+            config.direct_connect = direct_connect;
+        }
+        if let Some(ms) = self.punch_timeout_ms {
+            config.punch_timeout = Duration::from_millis(ms);
+        }
+    }
+}
+
+fn parse_env<T: std::str::FromStr>(
+    env: &impl Fn(&str) -> Option<String>,
+    name: &'static str,
+) -> Result<Option<T>, ConfigError> {
+    env(name)
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| ConfigError::InvalidEnv { name, value })
+        })
+        .transpose()
+}
+
+/// Layers the defaults, the config file and the environment.
+///
+/// An explicit `path` must exist; without one, `DEFAULT_CONFIG_PATH` is read
+/// only if present.
+pub fn load_config(
+    path: Option<&Path>,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<NorayConfig, ConfigError> {
+    let mut config = NorayConfig::default();
+
+    let source = match path {
+        Some(path) => Some(read_config(path)?),
+        None => match std::fs::read_to_string(DEFAULT_CONFIG_PATH) {
+            Ok(source) => Some(source),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                return Err(ConfigError::Io {
+                    path: PathBuf::from(DEFAULT_CONFIG_PATH),
+                    error: e,
+                });
+            }
+        },
+    };
+    if let Some(source) = source {
+        ConfigOverrides::from_toml(&source)?.apply(&mut config);
+    }
+
+    ConfigOverrides::from_env(env)?.apply(&mut config);
+    Ok(config)
+}
+
+fn read_config(path: &Path) -> Result<String, ConfigError> {
+    std::fs::read_to_string(path).map_err(|error| ConfigError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Parse(String),
+    InvalidEnv {
+        name: &'static str,
+        value: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "Failed to read {}: {}", path.display(), error),
+            Self::Parse(message) => write!(f, "Invalid config file: {}", message),
+            Self::InvalidEnv { name, value } => write!(f, "Invalid {}: '{}'", name, value),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
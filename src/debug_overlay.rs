@@ -0,0 +1,196 @@
+use bevy::prelude::*;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::SimulationTick;
+use crate::game::player::Player;
+use crate::network::ConnectionPath;
+use crate::sync::{NetworkStats, NetworkTime, RemotePlayerData, SnapshotBuffer};
+
+/// On-screen network health, toggled with a key: one line per remote OID
+/// with RTT, loss, bandwidth, newest frame and connection path, and a ghost
+/// sprite at each player's newest raw received position.
+///
+/// Needs [`NorayPlugin`](crate::NorayPlugin) for the data it shows.
+pub struct NetworkDebugPlugin {
+    pub toggle_key: KeyCode,
+}
+
+impl Default for NetworkDebugPlugin {
+    fn default() -> Self {
+        Self {
+            toggle_key: KeyCode::F3,
+        }
+    }
+}
+
+/// Whether the overlay is shown, and the key toggling it.
+#[derive(Resource, Debug)]
+pub struct NetworkDebugOverlay {
+    pub toggle_key: KeyCode,
+    pub visible: bool,
+}
+
+#[derive(Component)]
+struct OverlayText;
+
+/// Marks the sprite drawn at a player's raw received position.
+#[derive(Component)]
+struct Ghost {
+    oid: String,
+}
+
+const GHOST_COLOR: Color = Color::srgba(1.0, 1.0, 1.0, 0.35);
+
+impl Plugin for NetworkDebugPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(NetworkDebugOverlay {
+            toggle_key: self.toggle_key,
+            visible: false,
+        })
+        .add_systems(Startup, spawn_overlay_text)
+        .add_systems(
+            Update,
+            (toggle_overlay, update_overlay_text, update_ghosts).chain(),
+        );
+    }
+}
+
+fn spawn_overlay_text(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 16.0,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            right: Val::Px(10.0),
+            ..default()
+        })
+        .with_background_color(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+        OverlayText,
+    ));
+}
+
+fn toggle_overlay(keyboard: Res<ButtonInput<KeyCode>>, mut overlay: ResMut<NetworkDebugOverlay>) {
+    if keyboard.just_pressed(overlay.toggle_key) {
+        overlay.visible = !overlay.visible;
+    }
+}
+
+fn update_overlay_text(
+    overlay: Res<NetworkDebugOverlay>,
+    stats: Res<NetworkStats>,
+    network_time: Res<NetworkTime>,
+    tick: Res<SimulationTick>,
+    remote_data: Res<RemotePlayerData>,
+    players: Query<(&Player, &SnapshotBuffer)>,
+    mut text: Query<(&mut Text, &mut Visibility), With<OverlayText>>,
+) {
+    let Ok((mut text, mut visibility)) = text.get_single_mut() else {
+        return;
+    };
+
+    if !overlay.visible {
+        *visibility = Visibility::Hidden;
+        return;
+    }
+    *visibility = Visibility::Inherited;
+
+    let mut lines = format!("Tick {}", tick.0);
+    if let Some(host_tick) = network_time.host_tick() {
+        let _ = write!(lines, " (host ~{:.0})", host_tick);
+    }
+    lines.push_str("\nOID | RTT | loss | in/out kB/s | frame | path");
+
+    let mut oids: Vec<&String> = remote_data
+        .last_seen
+        .keys()
+        .filter(|oid| !remote_data.departed.contains(*oid))
+        .collect();
+    oids.sort();
+
+    for oid in oids {
+        let frame = players
+            .iter()
+            .find(|(player, _)| &player.oid == oid)
+            .and_then(|(_, buffer)| buffer.newest())
+            .map_or("-".to_string(), |snapshot| snapshot.state.frame.to_string());
+
+        let link = stats.peer_of(oid).and_then(|peer| stats.peer(peer));
+        let _ = match link {
+            Some(link) => write!(
+                lines,
+                "\n{} | {:.0} ms | {:.1}% | {:.1}/{:.1} | {} | {}",
+                oid,
+                link.totals.rtt.as_secs_f64() * 1000.0,
+                link.totals.loss() * 100.0,
+                link.bytes_received_per_second / 1000.0,
+                link.bytes_sent_per_second / 1000.0,
+                frame,
+                match link.totals.path {
+                    Some(ConnectionPath::Direct) => "direct",
+                    Some(ConnectionPath::Relay) => "relay",
+                    None => "-",
+                },
+            ),
+            None => write!(lines, "\n{} | - | - | - | {} | -", oid, frame),
+        };
+    }
+
+    text.sections[0].value = lines;
+}
+
+/// Keeps one ghost per remote player at its newest received position while
+/// the overlay is shown.
+fn update_ghosts(
+    mut commands: Commands,
+    overlay: Res<NetworkDebugOverlay>,
+    remote_data: Res<RemotePlayerData>,
+    mut ghosts: Query<(Entity, &Ghost, &mut Transform)>,
+) {
+    let mut placed = HashSet::new();
+
+    for (entity, ghost, mut transform) in ghosts.iter_mut() {
+        let raw = remote_data
+            .players
+            .get(&ghost.oid)
+            .filter(|_| overlay.visible && !remote_data.departed.contains(&ghost.oid));
+
+        match raw {
+            Some((x, y, ..)) => {
+                transform.translation.x = *x;
+                transform.translation.y = *y;
+                placed.insert(ghost.oid.clone());
+            }
+            None => commands.entity(entity).despawn_recursive(),
+        }
+    }
+
+    if !overlay.visible {
+        return;
+    }
+
+    for (oid, (x, y, ..)) in &remote_data.players {
+        if placed.contains(oid) || remote_data.departed.contains(oid) {
+            continue;
+        }
+
+        commands.spawn((
+            Ghost { oid: oid.clone() },
+            SpriteBundle {
+                sprite: Sprite {
+                    color: GHOST_COLOR,
+                    custom_size: Some(Vec2::new(50.0, 50.0)),
+                    ..default()
+                },
+                transform: Transform::from_xyz(*x, *y, 1.0),
+                ..default()
+            },
+        ));
+    }
+}
@@ -1,29 +1,70 @@
-use crate::game::player::{Player, Velocity};
+use crate::game::player::{MOVE_SPEED, Player, Velocity};
+use crate::local_player_data::LocalPlayerMarker;
+use crate::network::PlayerInput;
 use bevy::prelude::*;
 
+/// The local player's input this frame, as the input systems read it.
+pub fn read_input(keyboard: &ButtonInput<KeyCode>, dt: f32) -> PlayerInput {
+    let direction = if keyboard.pressed(KeyCode::KeyA) {
+        -1
+    } else if keyboard.pressed(KeyCode::KeyD) {
+        1
+    } else {
+        0
+    };
+
+    PlayerInput {
+        direction,
+        jump: keyboard.just_pressed(KeyCode::Space),
+        dt,
+    }
+}
+
+/// Local input gathered every frame until the next fixed tick consumes it, so
+/// a jump pressed between two ticks is not lost.
+#[derive(Resource, Debug, Default)]
+pub struct LocalInputLatch {
+    direction: i8,
+    jump: bool,
+}
+
+impl LocalInputLatch {
+    /// The input for the current fixed tick.
+    pub fn input(&self, dt: f32) -> PlayerInput {
+        PlayerInput {
+            direction: self.direction,
+            jump: self.jump,
+            dt,
+        }
+    }
+}
+
+pub fn latch_local_input(keyboard: Res<ButtonInput<KeyCode>>, mut latch: ResMut<LocalInputLatch>) {
+    let input = read_input(&keyboard, 0.0);
+    latch.direction = input.direction;
+    latch.jump |= input.jump;
+}
+
+/// Runs after every fixed tick, so a latched jump is applied exactly once.
+pub fn clear_latched_jump(mut latch: ResMut<LocalInputLatch>) {
+    latch.jump = false;
+}
+
 pub fn handle_local_input(
-    keyboard: Res<ButtonInput<KeyCode>>,
-    mut query: Query<&mut Velocity, With<Player>>,
+    latch: Res<LocalInputLatch>,
+    mut query: Query<&mut Velocity, (With<Player>, With<LocalPlayerMarker>)>,
 ) {
     for mut velocity in query.iter_mut() {
-        const SPEED: f32 = 300.0;
-
-        if keyboard.pressed(KeyCode::KeyA) {
-            velocity.x = -SPEED;
-        } else if keyboard.pressed(KeyCode::KeyD) {
-            velocity.x = SPEED;
-        } else {
-            velocity.x = 0.0;
-        }
+        velocity.x = latch.direction as f32 * MOVE_SPEED;
     }
 }
 
 pub fn handle_jump_input(
-    keyboard: Res<ButtonInput<KeyCode>>,
-    query: Query<Entity, With<Player>>,
+    latch: Res<LocalInputLatch>,
+    query: Query<Entity, (With<Player>, With<LocalPlayerMarker>)>,
     mut jump_events: EventWriter<JumpEvent>,
 ) {
-    if keyboard.just_pressed(KeyCode::Space) {
+    if latch.jump {
         for entity in query.iter() {
             jump_events.send(JumpEvent(entity));
         }
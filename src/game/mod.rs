@@ -1,8 +1,11 @@
 pub mod local_input;
 pub mod player;
 
-pub use local_input::{JumpEvent, handle_jump_input, handle_local_input};
+pub use local_input::{
+    JumpEvent, LocalInputLatch, clear_latched_jump, handle_jump_input, handle_local_input,
+    latch_local_input, read_input,
+};
 pub use player::{
-    IsJumping, Player, Velocity, apply_physics, apply_velocity, handle_jump_events, jump,
-    spawn_player,
+    IsJumping, Player, SPAWN_POSITION, Velocity, apply_physics, apply_velocity, handle_jump_events,
+    jump, simulate, spawn_player,
 };
@@ -1,6 +1,9 @@
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
+use crate::local_player_data::LocalPlayerMarker;
+use crate::network::PlayerInput;
+
 #[derive(Component, Clone)]
 pub struct Player {
     pub oid: String,
@@ -47,10 +50,41 @@ pub fn spawn_player(
 
 const GRAVITY: f32 = 900.0;
 const GROUND_LEVEL: f32 = 25.0;
-const MOVE_SPEED: f32 = 300.0;
+pub const MOVE_SPEED: f32 = 300.0;
 const JUMP_FORCE: f32 = 400.0;
+/// Where players enter the world.
+pub const SPAWN_POSITION: Vec3 = Vec3::new(0.0, 100.0, 0.0);
+
+/// Advances one player by one input, exactly like the local input and physics
+/// systems do over a frame. Used to simulate and re-simulate networked input.
+pub fn simulate(
+    transform: &mut Transform,
+    velocity: &mut Velocity,
+    is_jumping: &mut IsJumping,
+    input: &PlayerInput,
+) {
+    velocity.x = input.direction.signum() as f32 * MOVE_SPEED;
+
+    transform.translation.x += velocity.x * input.dt;
+    transform.translation.y += velocity.y * input.dt;
 
-pub fn apply_velocity(mut query: Query<(&mut Transform, &Velocity)>, time: Res<Time>) {
+    velocity.y -= GRAVITY * input.dt;
+    if transform.translation.y <= GROUND_LEVEL {
+        transform.translation.y = GROUND_LEVEL;
+        velocity.y = 0.0;
+        is_jumping.0 = false;
+    }
+
+    if input.jump && !is_jumping.0 {
+        velocity.y = JUMP_FORCE;
+        is_jumping.0 = true;
+    }
+}
+
+pub fn apply_velocity(
+    mut query: Query<(&mut Transform, &Velocity), With<LocalPlayerMarker>>,
+    time: Res<Time>,
+) {
     for (mut transform, velocity) in query.iter_mut() {
         transform.translation.x += velocity.x * time.delta_seconds();
         transform.translation.y += velocity.y * time.delta_seconds();
@@ -58,7 +92,7 @@ pub fn apply_velocity(mut query: Query<(&mut Transform, &Velocity)>, time: Res<T
 }
 
 pub fn apply_physics(
-    mut query: Query<(&mut Velocity, &mut IsJumping, &mut Transform)>,
+    mut query: Query<(&mut Velocity, &mut IsJumping, &mut Transform), With<LocalPlayerMarker>>,
     time: Res<Time>,
 ) {
     for (mut velocity, mut is_jumping, mut transform) in query.iter_mut() {
@@ -86,11 +120,11 @@ pub fn handle_jump_events(
     mut query: Query<(&mut Velocity, &mut IsJumping)>,
 ) {
     for event in events.read() {
-        if let Ok((mut velocity, mut is_jumping)) = query.get_mut(event.0) {
-            if !is_jumping.0 {
-                velocity.y = JUMP_FORCE;
-                is_jumping.0 = true;
-            }
+        if let Ok((mut velocity, mut is_jumping)) = query.get_mut(event.0)
+            && !is_jumping.0
+        {
+            velocity.y = JUMP_FORCE;
+            is_jumping.0 = true;
         }
     }
 }
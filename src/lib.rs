@@ -0,0 +1,14 @@
+pub mod config;
+pub mod debug_overlay;
+pub mod game;
+pub mod lobby;
+pub mod local_player_data;
+pub mod network;
+pub mod plugin;
+pub mod sync;
+
+pub use plugin::{
+    MessageReceived, NetworkMessages, NetworkingState, NorayHandshake, NorayPlugin, NorayRole,
+    NoraySet, NorayState, PlayerRegistrationInfo, SessionEventReceived, SimulationTick, StartGame,
+    StartHandshake, SyncMode, sync_local_state,
+};
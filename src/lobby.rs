@@ -0,0 +1,422 @@
+use bevy::input::ButtonState;
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::prelude::*;
+
+use crate::network::ConnectionPath;
+use crate::{
+    NetworkingState, NorayHandshake, NorayRole, NorayState, PlayerRegistrationInfo, StartGame,
+    StartHandshake,
+};
+
+/// Fewest and most players the host screen lets you pick.
+pub const MIN_PLAYERS: u32 = 2;
+pub const MAX_PLAYERS: u32 = 8;
+
+const BUTTON_COLOR: Color = Color::srgb(0.2, 0.2, 0.25);
+const HOVERED_COLOR: Color = Color::srgb(0.3, 0.3, 0.38);
+const PRESSED_COLOR: Color = Color::srgb(0.15, 0.45, 0.25);
+const FIELD_COLOR: Color = Color::srgb(0.1, 0.1, 0.12);
+
+/// Bevy UI screens for everything before the game starts: picking host or
+/// join, the player count, typing the host's OID, the waiting room and the
+/// failure screen.
+///
+/// Pair with [`NorayPlugin::lobby`](crate::NorayPlugin::lobby). With a role
+/// given up front it only shows the waiting room. Needs a UI camera.
+pub struct LobbyPlugin;
+
+/// What the lobby screens are editing.
+#[derive(Resource, Debug, Clone, PartialEq, Eq)]
+pub struct Lobby {
+    pub screen: LobbyScreen,
+    /// Player count to host with, host included.
+    pub players: u32,
+    /// Host OID typed on the join screen.
+    pub host_oid: String,
+}
+
+impl Default for Lobby {
+    fn default() -> Self {
+        Self {
+            screen: LobbyScreen::default(),
+            players: MIN_PLAYERS,
+            host_oid: String::new(),
+        }
+    }
+}
+
+/// The screen shown in [`NorayState::Lobby`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LobbyScreen {
+    #[default]
+    Menu,
+    Host,
+    Join,
+}
+
+/// What a lobby button does when pressed.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LobbyButton {
+    ShowHost,
+    ShowJoin,
+    /// Back to the menu, or to the lobby after a failure.
+    Back,
+    FewerPlayers,
+    MorePlayers,
+    Host,
+    Join,
+    PasteOid,
+    CopyOid,
+    Cancel,
+    Start,
+}
+
+#[derive(Component)]
+struct LobbyRoot;
+
+impl Plugin for LobbyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Lobby>().add_systems(
+            Update,
+            (
+                press_lobby_buttons,
+                type_host_oid.run_if(in_state(NorayState::Lobby)),
+                color_lobby_buttons,
+                rebuild_lobby,
+            )
+                .chain(),
+        );
+    }
+}
+
+fn press_lobby_buttons(
+    buttons: Query<(&Interaction, &LobbyButton), Changed<Interaction>>,
+    mut lobby: ResMut<Lobby>,
+    registration: Option<Res<PlayerRegistrationInfo>>,
+    handshake: Option<Res<NorayHandshake>>,
+    mut next_state: ResMut<NextState<NorayState>>,
+    mut start_handshake: EventWriter<StartHandshake>,
+    mut start_game: EventWriter<StartGame>,
+) {
+    for (interaction, button) in buttons.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+
+        match button {
+            LobbyButton::ShowHost => lobby.screen = LobbyScreen::Host,
+            LobbyButton::ShowJoin => lobby.screen = LobbyScreen::Join,
+            LobbyButton::Back => {
+                lobby.screen = LobbyScreen::Menu;
+                next_state.set(NorayState::Lobby);
+            }
+            LobbyButton::FewerPlayers => lobby.players = (lobby.players - 1).max(MIN_PLAYERS),
+            LobbyButton::MorePlayers => lobby.players = (lobby.players + 1).min(MAX_PLAYERS),
+            LobbyButton::Host => {
+                start_handshake.send(StartHandshake(NorayRole::Host {
+                    players: lobby.players,
+                }));
+            }
+            LobbyButton::Join => join(&lobby, &mut start_handshake),
+            LobbyButton::PasteOid => {
+                if let Some(text) = read_clipboard() {
+                    lobby.host_oid = text.trim().to_string();
+                }
+            }
+            LobbyButton::CopyOid => {
+                if let Some(registration) = &registration {
+                    write_clipboard(&registration.oid);
+                }
+            }
+            LobbyButton::Cancel => {
+                if let Some(handshake) = &handshake {
+                    handshake.cancel();
+                }
+            }
+            LobbyButton::Start => {
+                start_game.send(StartGame);
+            }
+        }
+    }
+}
+
+fn join(lobby: &Lobby, start_handshake: &mut EventWriter<StartHandshake>) {
+    if !lobby.host_oid.is_empty() {
+        start_handshake.send(StartHandshake(NorayRole::Join {
+            host_oid: lobby.host_oid.clone(),
+        }));
+    }
+}
+
+/// Text entry for the host OID field: printable characters, Backspace,
+/// Ctrl+V to paste and Enter to join.
+fn type_host_oid(
+    mut keys: EventReader<KeyboardInput>,
+    keyboard: Option<Res<ButtonInput<KeyCode>>>,
+    mut lobby: ResMut<Lobby>,
+    mut start_handshake: EventWriter<StartHandshake>,
+) {
+    if lobby.screen != LobbyScreen::Join {
+        keys.clear();
+        return;
+    }
+
+    let ctrl = keyboard.is_some_and(|keyboard| {
+        keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight])
+    });
+
+    for key in keys.read() {
+        if key.state != ButtonState::Pressed {
+            continue;
+        }
+
+        match &key.logical_key {
+            Key::Enter => join(&lobby, &mut start_handshake),
+            Key::Backspace => {
+                lobby.host_oid.pop();
+            }
+            Key::Character(text) if ctrl => {
+                if text.eq_ignore_ascii_case("v")
+                    && let Some(pasted) = read_clipboard()
+                {
+                    lobby.host_oid.push_str(pasted.trim());
+                }
+            }
+            Key::Character(text) => {
+                lobby.host_oid.extend(
+                    text.chars()
+                        .filter(|c| !c.is_control() && !c.is_whitespace()),
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+fn read_clipboard() -> Option<String> {
+    match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_text()) {
+        Ok(text) => Some(text),
+        Err(e) => {
+            warn!(error = %e, "Failed to read the clipboard");
+            None
+        }
+    }
+}
+
+fn write_clipboard(text: &str) {
+    match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(text)) {
+        Ok(()) => info!("Copied OID to the clipboard"),
+        Err(e) => warn!(error = %e, "Failed to write the clipboard"),
+    }
+}
+
+type LobbyButtonColorQuery<'w, 's> = Query<
+    'w,
+    's,
+    (&'static Interaction, &'static mut BackgroundColor),
+    (With<LobbyButton>, Changed<Interaction>),
+>;
+
+fn color_lobby_buttons(mut buttons: LobbyButtonColorQuery) {
+    for (interaction, mut color) in buttons.iter_mut() {
+        *color = match interaction {
+            Interaction::Pressed => PRESSED_COLOR,
+            Interaction::Hovered => HOVERED_COLOR,
+            Interaction::None => BUTTON_COLOR,
+        }
+        .into();
+    }
+}
+
+/// Respawns the lobby UI whenever what it shows changes, and removes it once
+/// in game.
+fn rebuild_lobby(
+    mut commands: Commands,
+    roots: Query<Entity, With<LobbyRoot>>,
+    state: Res<State<NorayState>>,
+    lobby: Res<Lobby>,
+    networking: Res<NetworkingState>,
+    registration: Option<Res<PlayerRegistrationInfo>>,
+    role: Option<Res<NorayRole>>,
+) {
+    let registration_changed = registration.as_ref().is_some_and(|r| r.is_changed());
+    if !(state.is_changed()
+        || lobby.is_changed()
+        || networking.is_changed()
+        || registration_changed)
+    {
+        return;
+    }
+
+    for root in roots.iter() {
+        commands.entity(root).despawn_recursive();
+    }
+
+    if *state.get() == NorayState::InGame {
+        return;
+    }
+
+    let oid = registration.map(|registration| registration.oid.clone());
+    let is_host = role.as_deref().is_some_and(NorayRole::is_host);
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(12.0),
+                    ..default()
+                },
+                ..default()
+            },
+            LobbyRoot,
+        ))
+        .with_children(|parent| match state.get() {
+            NorayState::Lobby => match lobby.screen {
+                LobbyScreen::Menu => {
+                    label(parent, "Bevy + Noray Multiplayer", 36.0);
+                    button(parent, "Host", LobbyButton::ShowHost);
+                    button(parent, "Join", LobbyButton::ShowJoin);
+                }
+                LobbyScreen::Host => {
+                    label(parent, "Host a game", 32.0);
+                    row(parent, |parent| {
+                        button(parent, "-", LobbyButton::FewerPlayers);
+                        label(parent, &format!("{} players", lobby.players), 24.0);
+                        button(parent, "+", LobbyButton::MorePlayers);
+                    });
+                    button(parent, "Host game", LobbyButton::Host);
+                    button(parent, "Back", LobbyButton::Back);
+                }
+                LobbyScreen::Join => {
+                    label(parent, "Host's OID", 32.0);
+                    text_field(parent, &lobby.host_oid);
+                    row(parent, |parent| {
+                        button(parent, "Paste", LobbyButton::PasteOid);
+                        button(parent, "Join", LobbyButton::Join);
+                    });
+                    button(parent, "Back", LobbyButton::Back);
+                }
+            },
+            NorayState::Registering | NorayState::Connecting => {
+                let status = if *state.get() == NorayState::Registering {
+                    "Registering with noray..."
+                } else {
+                    "Connecting..."
+                };
+                label(parent, status, 28.0);
+                button(parent, "Cancel", LobbyButton::Cancel);
+            }
+            NorayState::WaitingForPeers | NorayState::Ready if is_host => {
+                if let Some(oid) = &oid {
+                    row(parent, |parent| {
+                        label(parent, &format!("Your OID: {}", oid), 24.0);
+                        button(parent, "Copy", LobbyButton::CopyOid);
+                    });
+                }
+                label(
+                    parent,
+                    &format!(
+                        "Players: {}/{}",
+                        networking.peers_joined + 1,
+                        networking.peers_expected + 1
+                    ),
+                    24.0,
+                );
+                label(parent, "1. You (host)", 20.0);
+                for (index, peer) in networking.peers.iter().enumerate() {
+                    let path = match peer.path {
+                        ConnectionPath::Relay => "relay",
+                        ConnectionPath::Direct => "direct",
+                    };
+                    label(
+                        parent,
+                        &format!("{}. {}:{} ({})", index + 2, peer.host, peer.port, path),
+                        20.0,
+                    );
+                }
+
+                if *state.get() == NorayState::Ready {
+                    button(parent, "Start", LobbyButton::Start);
+                } else {
+                    button(parent, "Cancel", LobbyButton::Cancel);
+                }
+            }
+            NorayState::WaitingForPeers | NorayState::Ready => {
+                let host_oid = match role.as_deref() {
+                    Some(NorayRole::Join { host_oid }) => host_oid.as_str(),
+                    _ => "",
+                };
+                label(parent, &format!("Connected to {}", host_oid), 24.0);
+                label(parent, "Waiting for the host to start...", 24.0);
+            }
+            NorayState::Failed => {
+                label(
+                    parent,
+                    &format!("Connection failed: {}", networking.error_message),
+                    24.0,
+                );
+                button(parent, "Back", LobbyButton::Back);
+            }
+            NorayState::InGame => {}
+        });
+}
+
+fn label(parent: &mut ChildBuilder, text: &str, font_size: f32) {
+    parent.spawn(TextBundle::from_section(
+        text,
+        TextStyle {
+            font_size,
+            ..default()
+        },
+    ));
+}
+
+fn button(parent: &mut ChildBuilder, text: &str, action: LobbyButton) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    min_width: Val::Px(60.0),
+                    padding: UiRect::axes(Val::Px(16.0), Val::Px(8.0)),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                background_color: BUTTON_COLOR.into(),
+                ..default()
+            },
+            action,
+        ))
+        .with_children(|parent| label(parent, text, 24.0));
+}
+
+fn row(parent: &mut ChildBuilder, children: impl FnOnce(&mut ChildBuilder)) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                align_items: AlignItems::Center,
+                column_gap: Val::Px(12.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(children);
+}
+
+fn text_field(parent: &mut ChildBuilder, text: &str) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                min_width: Val::Px(320.0),
+                padding: UiRect::all(Val::Px(8.0)),
+                ..default()
+            },
+            background_color: FIELD_COLOR.into(),
+            ..default()
+        })
+        .with_children(|parent| label(parent, &format!("{}_", text), 24.0));
+}
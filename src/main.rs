@@ -1,320 +1,184 @@
-mod game;
-mod local_player_data;
-mod network;
-mod sync;
+use std::time::Duration;
 
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::log::LogPlugin;
 use bevy::prelude::*;
-use crossbeam_channel;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::thread;
-
-use game::player::{IsJumping, Velocity, spawn_player};
-use game::{
-    apply_physics, apply_velocity, handle_jump_events, handle_jump_input, handle_local_input,
-};
-use local_player_data::LocalPlayerMarker;
-use network::{
-    GameState, NorayConfig, register_only, register_udp_socket, send_game_state, start_udp_relay,
+use bevy::state::app::StatesPlugin;
+use clap::Parser;
+
+use bevy_noray::config::{Cli, Command};
+use bevy_noray::debug_overlay::NetworkDebugPlugin;
+use bevy_noray::game::player::{Player, SPAWN_POSITION, Velocity, spawn_player};
+use bevy_noray::game::{
+    JumpEvent, apply_physics, apply_velocity, handle_jump_events, handle_jump_input,
+    handle_local_input,
 };
-use sync::{
-    RemotePlayerData, RemoteUpdateReceiver, receive_remote_updates, update_remote_player_transforms,
+use bevy_noray::lobby::LobbyPlugin;
+use bevy_noray::local_player_data::LocalPlayerMarker;
+use bevy_noray::sync::{AppReplicateExt, Replicated};
+use bevy_noray::{
+    NetworkingState, NorayHandshake, NorayPlugin, NoraySet, NorayState, PlayerRegistrationInfo,
 };
 
-#[derive(Resource)]
-struct FrameCounter(Arc<AtomicU32>);
-
-#[derive(Resource)]
-struct SyncChannel(crossbeam_channel::Sender<GameState>);
+#[derive(Component)]
+struct StatusText;
 
-#[derive(Resource)]
-struct SyncReceiver(crossbeam_channel::Receiver<GameState>);
-
-#[derive(Resource, Clone)]
-pub struct PlayerRegistrationInfo {
-    pub oid: String,
-    pub pid: String,
+fn setup_game(mut commands: Commands) {
+    commands.spawn(Camera2dBundle::default());
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 20.0,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        }),
+        StatusText,
+    ));
 }
 
-#[derive(Resource, Default)]
-struct NetworkingState {
-    connected: bool,
-    error_message: String,
+/// The lobby covers everything before the game; this is the in-game hint.
+fn update_status_text(
+    state: Res<State<NorayState>>,
+    registration: Option<Res<PlayerRegistrationInfo>>,
+    mut query: Query<&mut Text, With<StatusText>>,
+) {
+    let status = match (state.get(), registration) {
+        (NorayState::InGame, Some(registration)) => format!(
+            "OID: {}\nA/D to move, Space to jump, F3 for network stats",
+            registration.oid
+        ),
+        _ => String::new(),
+    };
+
+    for mut text in query.iter_mut() {
+        text.sections[0].value.clone_from(&status);
+    }
 }
 
-fn setup_game(mut commands: Commands) {
-    commands.spawn(Camera2dBundle::default());
+fn cancel_on_escape(keyboard: Res<ButtonInput<KeyCode>>, handshake: Option<Res<NorayHandshake>>) {
+    if let Some(handshake) = handshake
+        && keyboard.just_pressed(KeyCode::Escape)
+    {
+        handshake.cancel();
+    }
 }
 
 fn spawn_local_player(
     mut commands: Commands,
     registration: Res<PlayerRegistrationInfo>,
-    local_players: Query<(), (With<game::player::Player>, With<LocalPlayerMarker>)>,
+    local_players: Query<(), (With<Player>, With<LocalPlayerMarker>)>,
 ) {
     if local_players.is_empty() {
         let local_player = spawn_player(
             &mut commands,
             registration.oid.clone(),
             true,
-            Vec3::new(0.0, 100.0, 0.0),
+            SPAWN_POSITION,
             Color::srgb(0.0, 0.0, 1.0),
         );
-        commands.entity(local_player).insert(LocalPlayerMarker);
+        commands
+            .entity(local_player)
+            .insert((LocalPlayerMarker, Replicated));
     }
 }
 
-fn main() {
-    let config = NorayConfig::default();
-
-    println!("=== Bevy + Noray Multiplayer (Localhost) ===\n");
-    println!("1. Host a game (2 players)");
-    println!("2. Host a game (3 players)");
-    println!("3. Host a game (4 players)");
-    println!("4. Join a game");
-    println!();
-
-    let mut choice = String::new();
-    std::io::stdin()
-        .read_line(&mut choice)
-        .expect("Failed to read input");
-    let choice = choice.trim();
-
-    let num_players = match choice {
-        "1" => 2,
-        "2" => 3,
-        "3" => 4,
-        "4" => {
-            println!("\nEnter host's OpenID:");
-            let mut host_oid = String::new();
-            std::io::stdin()
-                .read_line(&mut host_oid)
-                .expect("Failed to read OID");
-            let host_oid = host_oid.trim().to_string();
-
-            if host_oid.is_empty() {
-                eprintln!("Empty OID, exiting");
-                std::process::exit(1);
-            }
-
-            run_joiner(&config, &host_oid);
-            return;
-        }
-        _ => {
-            eprintln!("Invalid choice");
-            std::process::exit(1);
-        }
-    };
-
-    run_host(&config, num_players);
-}
-
-fn run_host(config: &NorayConfig, num_players: u32) {
-    println!("\n[1/3] Registering with noray...");
-    let (reg, stream) = match register_only(config) {
-        Ok(result) => result,
-        Err(e) => {
-            eprintln!("[ERROR] Registration failed: {}", e);
-            std::process::exit(1);
-        }
-    };
-    let player_oid = reg.oid.clone();
-    let player_pid = reg.pid.clone();
-    println!("[OK] Your OpenID: {}", player_oid);
-
-    println!("\n[2/3] Registering UDP (required before relay)...");
-    let udp_for_relay = match register_udp_socket(config, &player_pid) {
-        Ok(s) => s,
-        Err(e) => {
-            eprintln!("[ERROR] UDP registration failed: {}", e);
-            std::process::exit(1);
-        }
-    };
-    println!("[OK] UDP registered");
-
-    println!("\n[3/3] Waiting for {} players...", num_players - 1);
-    println!("Your OID: {}", player_oid);
-    println!("\nTell players your OID and keep this terminal open!");
-
-    let host = config.host.clone();
-    let peers = match network::noray_client::wait_for_connections(stream, host, num_players - 1) {
-        Ok(peers) => peers,
-        Err(e) => {
-            eprintln!("[ERROR] Failed to wait for connections: {}", e);
-            std::process::exit(1);
-        }
-    };
-
-    println!("\n[OK] All {} players connected!", num_players - 1);
-
-    let relay_port = peers[0].port;
-    let relay_host = peers[0].host.clone();
-    let relay_addr = format!("{}:{}", relay_host, relay_port);
-
-    println!("\n[UDP] Registering UDP socket...");
-    let socket_for_udp = udp_for_relay;
-    println!("[OK] UDP ready");
-
-    println!("\n[NETWORK] Starting UDP relay to {}...", relay_addr);
-
-    let (sync_tx, sync_rx) = crossbeam_channel::bounded(100);
-    let frame_counter = Arc::new(AtomicU32::new(0));
-
-    let socket_clone = socket_for_udp.try_clone().expect("Failed to clone socket");
-    let relay_addr_clone = relay_addr.clone();
-    thread::spawn(move || {
-        let sync_rx = sync_rx;
-        let socket = socket_clone;
-        loop {
-            if let Ok(state) = sync_rx.recv() {
-                let _ = send_game_state(&socket, &relay_addr_clone, &state);
-            }
-        }
-    });
-
-    let receiver = start_udp_relay(socket_for_udp, relay_port);
-    let receiver = Arc::new(receiver);
-
-    println!("\n=== Game Starting ===");
-    println!("Controls: A/D to move, Space to jump\n");
-
+/// Runs a dedicated host with no window, renderer or local player. It only
+/// relays between the joiners and runs the plugin's host-side systems.
+fn run_headless(noray: NorayPlugin) {
     App::new()
-        .add_plugins(DefaultPlugins)
-        .insert_resource(PlayerRegistrationInfo {
-            oid: player_oid,
-            pid: player_pid,
-        })
-        .insert_resource(RemoteUpdateReceiver { receiver })
-        .insert_resource(NetworkingState {
-            connected: true,
-            error_message: String::new(),
-        })
-        .insert_resource(RemotePlayerData::default())
-        .insert_resource(FrameCounter(frame_counter))
-        .insert_resource(SyncChannel(sync_tx))
-        .insert_resource(SyncReceiver(crossbeam_channel::bounded(100).1))
-        .add_event::<game::local_input::JumpEvent>()
-        .add_systems(Startup, (setup_game, spawn_local_player))
-        .add_systems(Update, handle_local_input)
-        .add_systems(Update, handle_jump_input)
-        .add_systems(Update, apply_velocity)
-        .add_systems(Update, apply_physics)
-        .add_systems(Update, handle_jump_events)
-        .add_systems(Update, sync_local_state)
-        .add_systems(Update, receive_remote_updates)
-        .add_systems(Update, update_remote_player_transforms)
+        .add_plugins((
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+                1.0 / noray.tick_rate,
+            ))),
+            StatesPlugin,
+            LogPlugin::default(),
+        ))
+        .add_plugins(noray)
+        .add_systems(OnEnter(NorayState::WaitingForPeers), log_host_oid)
+        .add_systems(OnEnter(NorayState::InGame), log_in_game)
+        .add_systems(OnEnter(NorayState::Failed), exit_on_failure)
+        .add_systems(
+            Update,
+            log_peers_joined.run_if(resource_changed::<NetworkingState>),
+        )
         .run();
 }
 
-fn run_joiner(config: &NorayConfig, host_oid: &str) {
-    println!("\n[1/3] Registering with noray...");
-    let (reg, joiner_stream) = match register_only(config) {
-        Ok(result) => result,
-        Err(e) => {
-            eprintln!("[ERROR] Registration failed: {}", e);
-            std::process::exit(1);
-        }
-    };
-    let player_oid = reg.oid.clone();
-    let player_pid = reg.pid.clone();
-    println!("[OK] Your OpenID: {}", player_oid);
-
-    println!("\n[2/3] Registering UDP (required before connect-relay)...");
-    let udp_socket = match register_udp_socket(config, &player_pid) {
-        Ok(s) => s,
-        Err(e) => {
-            eprintln!("[ERROR] UDP registration failed: {}", e);
-            std::process::exit(1);
-        }
-    };
-    println!("[OK] UDP registered");
-
-    println!("\n[3/3] Connecting to host: {}...", host_oid);
-    let result = network::noray_client::connect_to_relay_with_stream(joiner_stream, host_oid);
-
-    let (relay_port, relay_host) = match result {
-        Ok((port, host)) => (port, host),
-        Err(e) => {
-            eprintln!("[ERROR] Connection failed: {}", e);
-            std::process::exit(1);
-        }
-    };
-
-    println!("\n[OK] Got relay port: {}", relay_port);
-    let relay_addr = format!("{}:{}", relay_host, relay_port);
+fn log_host_oid(registration: Option<Res<PlayerRegistrationInfo>>) {
+    if let Some(registration) = registration {
+        info!(oid = %registration.oid, "Hosting; share this OID with the players");
+    }
+}
 
-    println!("\n[UDP] Registering UDP socket...");
-    println!("[OK] UDP ready");
+fn log_peers_joined(networking: Res<NetworkingState>) {
+    if networking.peers_expected > 0 && !networking.connected {
+        info!(
+            joined = networking.peers_joined,
+            expected = networking.peers_expected,
+            "Waiting for players"
+        );
+    }
+}
 
-    println!("\n[NETWORK] Starting UDP relay to {}...", relay_addr);
+fn log_in_game(networking: Res<NetworkingState>) {
+    info!(players = networking.peers_expected, "All players joined");
+}
 
-    let (sync_tx, sync_rx) = crossbeam_channel::bounded(100);
-    let frame_counter = Arc::new(AtomicU32::new(0));
+fn exit_on_failure(networking: Res<NetworkingState>, mut exit: EventWriter<AppExit>) {
+    error!(error = %networking.error_message, "Hosting failed");
+    exit.send(AppExit::from_code(1));
+}
 
-    let socket_clone = udp_socket.try_clone().expect("Failed to clone socket");
-    let relay_addr_clone = relay_addr.clone();
-    thread::spawn(move || {
-        let sync_rx = sync_rx;
-        let socket = socket_clone;
-        loop {
-            if let Ok(state) = sync_rx.recv() {
-                let _ = send_game_state(&socket, &relay_addr_clone, &state);
-            }
-        }
+fn main() {
+    let cli = Cli::parse();
+    let config = cli.noray_config().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
     });
 
-    let receiver = start_udp_relay(udp_socket, relay_port);
-    let receiver = Arc::new(receiver);
+    if cli.headless {
+        let Some(Command::Host { players }) = cli.command else {
+            eprintln!("--headless requires the host subcommand");
+            std::process::exit(2);
+        };
+
+        run_headless(NorayPlugin::dedicated_host(config, players));
+        return;
+    }
 
-    println!("\n=== Game Starting ===");
-    println!("Controls: A/D to move, Space to jump\n");
+    let noray = match cli.command {
+        Some(Command::Host { players }) => NorayPlugin::host(config, players),
+        Some(Command::Join { host_oid }) => NorayPlugin::join(config, host_oid),
+        None => NorayPlugin::lobby(config),
+    };
 
     App::new()
         .add_plugins(DefaultPlugins)
-        .insert_resource(PlayerRegistrationInfo {
-            oid: player_oid,
-            pid: player_pid,
-        })
-        .insert_resource(RemoteUpdateReceiver { receiver })
-        .insert_resource(NetworkingState {
-            connected: true,
-            error_message: String::new(),
-        })
-        .insert_resource(RemotePlayerData::default())
-        .insert_resource(FrameCounter(frame_counter))
-        .insert_resource(SyncChannel(sync_tx))
-        .insert_resource(SyncReceiver(crossbeam_channel::bounded(100).1))
-        .add_event::<game::local_input::JumpEvent>()
-        .add_systems(Startup, (setup_game, spawn_local_player))
-        .add_systems(Update, handle_local_input)
-        .add_systems(Update, handle_jump_input)
-        .add_systems(Update, apply_velocity)
-        .add_systems(Update, apply_physics)
-        .add_systems(Update, handle_jump_events)
-        .add_systems(Update, sync_local_state)
-        .add_systems(Update, receive_remote_updates)
-        .add_systems(Update, update_remote_player_transforms)
+        .add_plugins(noray)
+        .add_plugins((LobbyPlugin, NetworkDebugPlugin::default()))
+        .replicate::<Velocity>()
+        .add_event::<JumpEvent>()
+        .add_systems(Startup, setup_game)
+        .add_systems(OnEnter(NorayState::InGame), spawn_local_player)
+        .add_systems(Update, (update_status_text, cancel_on_escape))
+        .add_systems(
+            FixedUpdate,
+            (
+                handle_local_input,
+                handle_jump_input,
+                apply_velocity,
+                apply_physics,
+                handle_jump_events,
+            )
+                .chain()
+                .before(NoraySet::Send),
+        )
         .run();
 }
-
-fn sync_local_state(
-    query: Query<(&Transform, &Velocity, &IsJumping), With<LocalPlayerMarker>>,
-    counter: Res<FrameCounter>,
-    sync_tx: Res<SyncChannel>,
-    registration: Res<PlayerRegistrationInfo>,
-) {
-    for (transform, velocity, is_jumping) in query.iter() {
-        let frame = counter.0.fetch_add(1, Ordering::SeqCst);
-
-        let state = GameState {
-            oid: registration.oid.clone(),
-            frame,
-            x: transform.translation.x,
-            y: transform.translation.y,
-            vx: velocity.x,
-            vy: velocity.y,
-            is_jumping: is_jumping.0,
-        };
-
-        let _ = sync_tx.0.send(state);
-    }
-}
@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use super::packet_handler::{TimePing, TimePong};
+
+/// How often every peer is pinged.
+pub const PING_INTERVAL: Duration = Duration::from_millis(500);
+/// Recent exchanges considered when estimating a peer's clock offset.
+const SAMPLE_WINDOW: usize = 8;
+
+/// What the session currently knows about a peer's clock.
+#[derive(Debug, Clone, Copy)]
+pub struct ClockSample {
+    pub peer: SocketAddr,
+    /// Round-trip time of the newest ping.
+    pub rtt: Duration,
+    /// Peer clock minus local clock, in seconds.
+    pub offset: f64,
+    /// The peer's simulation tick at `remote_tick_at`.
+    pub remote_tick: u32,
+    /// The local instant the peer reported `remote_tick` at.
+    pub remote_tick_at: Instant,
+}
+
+/// Microsecond clock shared by everything on one session socket.
+#[derive(Debug, Clone, Copy)]
+pub struct SessionClock {
+    epoch: Instant,
+}
+
+impl SessionClock {
+    pub fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+        }
+    }
+
+    pub fn now(&self) -> u64 {
+        self.epoch.elapsed().as_micros() as u64
+    }
+
+    pub fn ping(&self) -> TimePing {
+        TimePing {
+            sent_at: self.now(),
+        }
+    }
+
+    pub fn pong(&self, ping: TimePing, tick: u32) -> TimePong {
+        TimePong {
+            ping_sent_at: ping.sent_at,
+            replied_at: self.now(),
+            tick,
+        }
+    }
+
+    fn instant(&self, micros: f64) -> Instant {
+        if micros >= 0.0 {
+            self.epoch + Duration::from_micros(micros as u64)
+        } else {
+            self.epoch - Duration::from_micros(-micros as u64)
+        }
+    }
+}
+
+impl Default for SessionClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Clock estimate for one peer from its recent pongs.
+#[derive(Debug, Default)]
+pub struct PeerClock {
+    /// (round trip, offset) of recent exchanges, in microseconds.
+    samples: VecDeque<(u64, f64)>,
+}
+
+impl PeerClock {
+    /// Folds in a pong received now. The offset is taken from the exchange
+    /// with the shortest round trip in the window, which is the least skewed
+    /// by queuing delay.
+    pub fn receive(
+        &mut self,
+        clock: &SessionClock,
+        peer: SocketAddr,
+        pong: TimePong,
+    ) -> ClockSample {
+        let received_at = clock.now();
+        let rtt = received_at.saturating_sub(pong.ping_sent_at);
+        let midpoint = (pong.ping_sent_at + received_at) as f64 / 2.0;
+
+        self.samples
+            .push_back((rtt, pong.replied_at as f64 - midpoint));
+        while self.samples.len() > SAMPLE_WINDOW {
+            self.samples.pop_front();
+        }
+
+        let offset = self
+            .samples
+            .iter()
+            .min_by_key(|(rtt, _)| *rtt)
+            .map_or(0.0, |(_, offset)| *offset);
+
+        ClockSample {
+            peer,
+            rtt: Duration::from_micros(rtt),
+            offset: offset / 1e6,
+            remote_tick: pong.tick,
+            remote_tick_at: clock.instant(pong.replied_at as f64 - offset),
+        }
+    }
+}
@@ -0,0 +1,507 @@
+use crossbeam_channel::Sender;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::time::timeout;
+use tracing::{Instrument, debug, info, info_span, instrument, warn};
+
+use super::host_session::{HostSession, SessionChannels};
+use super::noray_client::{ConnectionPath, NorayConfig, PeerInfo, RegistrationInfo};
+use super::noray_protocol::{NorayError, NorayMessage};
+
+const REGISTER_TIMEOUT: Duration = Duration::from_secs(5);
+const WAIT_FOR_PEERS_TIMEOUT: Duration = Duration::from_secs(300);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+const UDP_REGISTER_ATTEMPTS: usize = 5;
+const UDP_REGISTER_TIMEOUT: Duration = Duration::from_millis(200);
+const PUNCH_INTERVAL: Duration = Duration::from_millis(100);
+const PUNCH_PACKET: &[u8] = b"noray-punch";
+/// Sent by a joiner over the path it chose, followed by its OID, until the
+/// host answers with [`WELCOME_PACKET`]. The host only counts joiners that
+/// said hello, so both sides agree on the path.
+const HELLO_PREFIX: &[u8] = b"noray-hello ";
+pub(super) const WELCOME_PACKET: &[u8] = b"noray-welcome";
+
+/// Progress reported by the background handshake task.
+pub enum HandshakeProgress {
+    Registered(RegistrationInfo),
+    /// The joiner at `index` reached the host, sent just before the matching
+    /// `WaitingForPeers` update. Sent again under the same `index` if the
+    /// joiner switches to another path.
+    PeerJoined {
+        index: usize,
+        peer: PeerInfo,
+    },
+    WaitingForPeers {
+        joined: usize,
+        expected: usize,
+    },
+    Connecting,
+    Connected(Box<NoraySession>),
+    Failed(NorayError),
+}
+
+/// A fully established UDP session, ready to be handed to the sync systems.
+pub struct NoraySession {
+    pub registration: RegistrationInfo,
+    pub channels: SessionChannels,
+}
+
+/// Line-oriented TCP connection to the noray server.
+struct NorayConnection {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl NorayConnection {
+    async fn open(config: &NorayConfig) -> Result<Self, NorayError> {
+        let tcp_addr = format!("{}:{}", config.host, config.tcp_port);
+        info!(address = %tcp_addr, "Connecting to noray");
+
+        let stream = TcpStream::connect(&tcp_addr).await?;
+        let (reader, writer) = stream.into_split();
+
+        Ok(Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        })
+    }
+
+    async fn send(&mut self, command: &str) -> Result<(), NorayError> {
+        debug!(command, "Sending to noray");
+        self.writer
+            .write_all(format!("{}\n", command).as_bytes())
+            .await?;
+        Ok(())
+    }
+
+    async fn next_message(&mut self) -> Result<NorayMessage, NorayError> {
+        match self.lines.next_line().await? {
+            Some(line) => {
+                debug!(line = line.trim(), "Received from noray");
+                NorayMessage::parse(&line)
+            }
+            None => Err(NorayError::ConnectionClosed),
+        }
+    }
+}
+
+#[instrument(name = "register", skip_all)]
+async fn register(config: &NorayConfig) -> Result<(RegistrationInfo, NorayConnection), NorayError> {
+    let mut connection = NorayConnection::open(config).await?;
+    connection.send("register-host").await?;
+
+    let registration = timeout(REGISTER_TIMEOUT, async {
+        let mut oid = None;
+        let mut pid = None;
+
+        while oid.is_none() || pid.is_none() {
+            match connection.next_message().await?.into_result()? {
+                NorayMessage::SetOid(value) => oid = Some(value),
+                NorayMessage::SetPid(value) => pid = Some(value),
+                _ => {}
+            }
+        }
+
+        match (oid, pid) {
+            (Some(oid), Some(pid)) => Ok(RegistrationInfo { oid, pid }),
+            _ => Err(NorayError::MissingRegistration),
+        }
+    })
+    .await
+    .map_err(|_| NorayError::Timeout("registration"))??;
+
+    info!(oid = %registration.oid, pid = %registration.pid, "Registered with noray");
+    Ok((registration, connection))
+}
+
+#[instrument(name = "register_udp", skip_all, fields(pid))]
+async fn register_udp(
+    config: &NorayConfig,
+    pid: &str,
+) -> Result<tokio::net::UdpSocket, NorayError> {
+    let socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await?;
+
+    let udp_addr = format!("{}:{}", config.host, config.udp_port);
+    debug!(address = %udp_addr, "Registering UDP");
+
+    let mut buf = [0u8; 1024];
+    let mut registered = false;
+
+    for _ in 0..UDP_REGISTER_ATTEMPTS {
+        socket.send_to(pid.as_bytes(), &udp_addr).await?;
+
+        if let Ok(Ok((len, _))) = timeout(UDP_REGISTER_TIMEOUT, socket.recv_from(&mut buf)).await {
+            let response = String::from_utf8_lossy(&buf[..len]);
+            info!(response = %response, "UDP registered");
+            registered = true;
+            break;
+        }
+    }
+
+    if !registered {
+        warn!("No UDP registration response, continuing");
+    }
+
+    Ok(socket)
+}
+
+/// Hands the handshake socket over to the blocking UDP threads.
+fn into_game_socket(socket: tokio::net::UdpSocket) -> Result<UdpSocket, NorayError> {
+    let socket = socket.into_std()?;
+    socket.set_nonblocking(false)?;
+    socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+
+    Ok(socket)
+}
+
+/// Sends punch packets to `target` until one arrives back from it, opening a
+/// direct path through both NATs. Returns `false` if `deadline` passes first.
+async fn punch(socket: &tokio::net::UdpSocket, target: SocketAddr, deadline: Duration) -> bool {
+    let attempt = async {
+        let mut buf = [0u8; 64];
+        let mut interval = tokio::time::interval(PUNCH_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let _ = socket.send_to(PUNCH_PACKET, target).await;
+                }
+                received = socket.recv_from(&mut buf) => {
+                    if let Ok((len, from)) = received
+                        && from == target
+                        && &buf[..len] == PUNCH_PACKET
+                    {
+                        // Make sure the other side sees us too before it gives up.
+                        for _ in 0..3 {
+                            let _ = socket.send_to(PUNCH_PACKET, target).await;
+                        }
+                        return;
+                    }
+                }
+            }
+        }
+    };
+
+    timeout(deadline, attempt).await.is_ok()
+}
+
+/// Says hello to the host at `target` until it welcomes us. Returns `false`
+/// if `deadline` passes first.
+async fn confirm_path(
+    socket: &tokio::net::UdpSocket,
+    target: SocketAddr,
+    oid: &str,
+    deadline: Duration,
+) -> bool {
+    let hello = [HELLO_PREFIX, oid.as_bytes()].concat();
+
+    let attempt = async {
+        let mut buf = [0u8; 64];
+        let mut interval = tokio::time::interval(PUNCH_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let _ = socket.send_to(&hello, target).await;
+                }
+                received = socket.recv_from(&mut buf) => {
+                    if let Ok((len, from)) = received
+                        && from == target
+                        && &buf[..len] == WELCOME_PACKET
+                    {
+                        return;
+                    }
+                }
+            }
+        }
+    };
+
+    timeout(deadline, attempt).await.is_ok()
+}
+
+/// The OID in a joiner's hello packet.
+pub(super) fn parse_hello(packet: &[u8]) -> Option<&str> {
+    packet
+        .strip_prefix(HELLO_PREFIX)
+        .and_then(|oid| std::str::from_utf8(oid).ok())
+        .filter(|oid| !oid.is_empty())
+}
+
+/// Registers as a host and waits until `num_players - 1` peers have joined.
+#[instrument(name = "host_handshake", skip_all, fields(players = num_players))]
+pub async fn host(
+    config: NorayConfig,
+    num_players: u32,
+    progress: Sender<HandshakeProgress>,
+) -> Result<NoraySession, NorayError> {
+    let (registration, mut connection) = register(&config).await?;
+    let _ = progress.send(HandshakeProgress::Registered(registration.clone()));
+
+    let udp_for_relay = register_udp(&config, &registration.pid).await?;
+
+    let expected = num_players.saturating_sub(1) as usize;
+    info!(expected, "Waiting for players");
+    let _ = progress.send(HandshakeProgress::WaitingForPeers {
+        joined: 0,
+        expected,
+    });
+
+    let peers = timeout(
+        WAIT_FOR_PEERS_TIMEOUT,
+        async {
+            // Joiners, counted once they said hello over their path.
+            let mut peers: Vec<PeerInfo> = Vec::new();
+            // Public addresses of joiners trying to connect directly, and
+            // until when we punch towards them.
+            let mut direct: Vec<SocketAddr> = Vec::new();
+            let mut punching: Vec<(SocketAddr, Instant)> = Vec::new();
+            let mut interval = tokio::time::interval(PUNCH_INTERVAL);
+            let mut buf = [0u8; 256];
+
+            while peers.len() < expected {
+                tokio::select! {
+                    message = connection.next_message() => match message {
+                        Ok(NorayMessage::ConnectRelay(port)) => {
+                            debug!(relay_port = port, "Player connecting through the relay");
+                            // Opens our NAT to the relay before the joiner's hello.
+                            let _ = udp_for_relay
+                                .send_to(PUNCH_PACKET, (config.host.as_str(), port))
+                                .await;
+                        }
+                        Ok(NorayMessage::Connect(address)) => {
+                            debug!(address = %address, "Punching through");
+                            // A failed punch is not fatal: the joiner falls back
+                            // to connect-relay and says hello over the relay.
+                            direct.push(address);
+                            punching.push((address, Instant::now() + config.punch_timeout));
+                        }
+                        Ok(NorayMessage::Error { message, .. }) => {
+                            return Err(NorayError::from_server(&message));
+                        }
+                        Ok(_) => {}
+                        Err(NorayError::MalformedPort(port)) => {
+                            warn!(port = %port, "Invalid relay port");
+                        }
+                        Err(e) => return Err(e),
+                    },
+                    _ = interval.tick() => {
+                        let now = Instant::now();
+                        punching.retain(|(_, until)| *until > now);
+                        for (address, _) in &punching {
+                            let _ = udp_for_relay.send_to(PUNCH_PACKET, address).await;
+                        }
+                    }
+                    received = udp_for_relay.recv_from(&mut buf) => {
+                        let Ok((len, from)) = received else {
+                            continue;
+                        };
+                        let Some(oid) = parse_hello(&buf[..len]) else {
+                            continue;
+                        };
+                        let _ = udp_for_relay.send_to(WELCOME_PACKET, from).await;
+                        punching.retain(|(address, _)| *address != from);
+
+                        let peer = PeerInfo {
+                            port: from.port(),
+                            host: from.ip().to_string(),
+                            path: if direct.contains(&from) {
+                                ConnectionPath::Direct
+                            } else {
+                                ConnectionPath::Relay
+                            },
+                            oid: Some(oid.to_string()),
+                        };
+
+                        let index = match peers.iter().position(|joined| joined.oid == peer.oid) {
+                            Some(index) if peers[index].port == peer.port
+                                && peers[index].host == peer.host => continue,
+                            Some(index) => {
+                                // Our welcome over the old path got lost and the
+                                // joiner moved on; follow it.
+                                info!(oid, path = ?peer.path, "Player switched path");
+                                peers[index] = peer.clone();
+                                index
+                            }
+                            None => {
+                                info!(
+                                    player = peers.len() + 1,
+                                    oid,
+                                    address = %from,
+                                    path = ?peer.path,
+                                    "Player connected"
+                                );
+                                peers.push(peer.clone());
+                                peers.len() - 1
+                            }
+                        };
+
+                        let _ = progress.send(HandshakeProgress::PeerJoined { index, peer });
+                        let _ = progress.send(HandshakeProgress::WaitingForPeers {
+                            joined: peers.len(),
+                            expected,
+                        });
+                    }
+                }
+            }
+
+            Ok(peers)
+        }
+        .instrument(info_span!("wait_for_peers", expected)),
+    )
+    .await
+    .map_err(|_| NorayError::Timeout("players"))??;
+
+    info!("All players connected");
+    let _ = progress.send(HandshakeProgress::Connecting);
+
+    let session =
+        HostSession::new(into_game_socket(udp_for_relay)?, &peers).map_err(NorayError::Udp)?;
+
+    for peer in session.peers() {
+        debug!(peer = %peer, "Relaying to peer");
+    }
+
+    Ok(NoraySession {
+        registration,
+        channels: session.start(),
+    })
+}
+
+/// Registers with noray and connects to the host registered under `host_oid`.
+#[instrument(name = "join_handshake", skip_all, fields(host_oid = %host_oid))]
+pub async fn join(
+    config: NorayConfig,
+    host_oid: String,
+    progress: Sender<HandshakeProgress>,
+) -> Result<NoraySession, NorayError> {
+    let (registration, mut connection) = register(&config).await?;
+    let _ = progress.send(HandshakeProgress::Registered(registration.clone()));
+
+    let udp_socket = register_udp(&config, &registration.pid).await?;
+
+    let _ = progress.send(HandshakeProgress::Connecting);
+
+    let oid = &registration.oid;
+    let direct = if config.direct_connect {
+        connect_direct(&mut connection, &udp_socket, &config, &host_oid, oid).await?
+    } else {
+        None
+    };
+
+    let peer = match direct {
+        Some(peer) => peer,
+        None => connect_relay(&mut connection, &udp_socket, &config, &host_oid, oid).await?,
+    };
+
+    info!(host = %peer.host, port = peer.port, path = ?peer.path, "Starting UDP session");
+
+    let session =
+        HostSession::new(into_game_socket(udp_socket)?, &[peer]).map_err(NorayError::Udp)?;
+
+    Ok(NoraySession {
+        registration,
+        channels: session.start(),
+    })
+}
+
+/// Asks noray for the host's public address, punches through to it and
+/// confirms the path with the host.
+///
+/// Returns `None` when that fails and the caller should use the relay.
+#[instrument(name = "connect_direct", skip_all)]
+async fn connect_direct(
+    connection: &mut NorayConnection,
+    socket: &tokio::net::UdpSocket,
+    config: &NorayConfig,
+    host_oid: &str,
+    oid: &str,
+) -> Result<Option<PeerInfo>, NorayError> {
+    connection.send(&format!("connect {}", host_oid)).await?;
+
+    let address = timeout(CONNECT_TIMEOUT, async {
+        loop {
+            if let NorayMessage::Connect(address) =
+                connection.next_message().await?.into_result()?
+            {
+                return Ok::<_, NorayError>(address);
+            }
+        }
+    })
+    .await
+    .map_err(|_| NorayError::Timeout("response"));
+
+    let address = match address {
+        Ok(Ok(address)) => address,
+        Ok(Err(e @ NorayError::UnknownOid(_))) => return Err(e),
+        Ok(Err(e)) | Err(e) => {
+            info!(error = %e, "Direct connect unavailable, using relay");
+            return Ok(None);
+        }
+    };
+
+    debug!(address = %address, "Punching through");
+
+    if !punch(socket, address, config.punch_timeout).await {
+        warn!(address = %address, "Punch-through failed, using relay");
+        return Ok(None);
+    }
+
+    if !confirm_path(socket, address, oid, config.punch_timeout).await {
+        warn!(address = %address, "Host did not confirm the direct path, using relay");
+        return Ok(None);
+    }
+
+    info!(address = %address, "Connected directly");
+
+    Ok(Some(PeerInfo {
+        port: address.port(),
+        host: address.ip().to_string(),
+        path: ConnectionPath::Direct,
+        oid: None,
+    }))
+}
+
+#[instrument(name = "connect_relay", skip_all)]
+async fn connect_relay(
+    connection: &mut NorayConnection,
+    socket: &tokio::net::UdpSocket,
+    config: &NorayConfig,
+    host_oid: &str,
+    oid: &str,
+) -> Result<PeerInfo, NorayError> {
+    connection
+        .send(&format!("connect-relay {}", host_oid))
+        .await?;
+
+    let relay_port = timeout(CONNECT_TIMEOUT, async {
+        loop {
+            if let NorayMessage::ConnectRelay(port) =
+                connection.next_message().await?.into_result()?
+            {
+                return Ok::<_, NorayError>(port);
+            }
+        }
+    })
+    .await
+    .map_err(|_| NorayError::Timeout("response"))??;
+
+    info!(relay_port, "Got relay port");
+
+    let relay = tokio::net::lookup_host((config.host.as_str(), relay_port))
+        .await?
+        .next()
+        .ok_or_else(|| NorayError::MalformedAddress(config.host.clone()))?;
+    if !confirm_path(socket, relay, oid, CONNECT_TIMEOUT).await {
+        return Err(NorayError::Timeout("host welcome"));
+    }
+
+    Ok(PeerInfo {
+        port: relay_port,
+        host: config.host.clone(),
+        path: ConnectionPath::Relay,
+        oid: None,
+    })
+}
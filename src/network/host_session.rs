@@ -0,0 +1,620 @@
+use crossbeam_channel::{Receiver, Sender, TrySendError, select};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+use tracing::{debug, info, info_span, warn};
+
+use super::clock::{ClockSample, PING_INTERVAL, PeerClock, SessionClock};
+use super::handshake::{WELCOME_PACKET, parse_hello};
+use super::noray_client::{ConnectionPath, PeerInfo};
+use super::packet_handler::{
+    AuthoritativeState, Channel, ChannelMessage, ConnectionStats, GameState, GameStatePacket,
+    InputCommand, ReliableLink, RollbackInput,
+};
+use super::protocol::{MAX_DATAGRAM_SIZE, NetMessage, ProtocolError};
+use super::snapshot::{SnapshotAck, SnapshotLink};
+
+type Links = Arc<Mutex<HashMap<SocketAddr, ReliableLink>>>;
+/// Peers still in the session; joiners that left are removed.
+type Peers = Arc<Mutex<Vec<SocketAddr>>>;
+type Snapshots = Arc<Mutex<HashMap<SocketAddr, SnapshotLink>>>;
+
+/// The session socket, counting every datagram sent into [`ConnectionStats`].
+struct TrackedSocket {
+    socket: UdpSocket,
+    stats: ConnectionStats,
+}
+
+impl TrackedSocket {
+    fn send_to(&self, bytes: &[u8], peer: &SocketAddr) -> io::Result<usize> {
+        let sent = self.socket.send_to(bytes, peer)?;
+        self.stats.sent(*peer, sent);
+        Ok(sent)
+    }
+}
+
+/// Host side of a star-topology session.
+///
+/// Every joiner reaches the host through its own noray relay port, and joiners
+/// never see each other directly. The host therefore sends its own state to
+/// every peer and re-broadcasts each peer's state to all the others.
+///
+/// Joiners run the same session with the host as their only peer, so there is
+/// nobody to re-broadcast to.
+pub struct HostSession {
+    socket: UdpSocket,
+    peers: Vec<SocketAddr>,
+    paths: Vec<ConnectionPath>,
+    /// See [`PeerInfo::oid`].
+    oids: Vec<Option<String>>,
+}
+
+/// Channels connecting the app to a running [`HostSession`].
+pub struct SessionChannels {
+    /// Local `GameState`s, forwarded to every peer.
+    pub sync_tx: Sender<GameState>,
+    /// `GameState`s of all remote peers.
+    pub receiver: Receiver<GameState>,
+    /// Local channel messages, forwarded to every peer.
+    pub message_tx: Sender<ChannelMessage>,
+    /// Channel messages from all remote peers.
+    pub message_rx: Receiver<ChannelMessage>,
+    /// Local inputs, sent to the host in host-authoritative mode.
+    pub input_tx: Sender<InputCommand>,
+    /// Inputs from joiners, received by the host in host-authoritative mode.
+    pub inputs: Receiver<InputCommand>,
+    /// Host-simulated player states, sent to every peer.
+    pub authoritative_tx: Sender<AuthoritativeState>,
+    /// Host-simulated player states received from the host. They are also
+    /// delivered on `receiver` so remote players render as usual.
+    pub authoritative_rx: Receiver<AuthoritativeState>,
+    /// Local per-frame inputs, forwarded to every peer in rollback mode.
+    pub rollback_tx: Sender<RollbackInput>,
+    /// Per-frame inputs of all remote peers in rollback mode.
+    pub rollback_rx: Receiver<RollbackInput>,
+    /// Notable things happening on the session, such as incompatible peers.
+    pub events: Receiver<SessionEvent>,
+    /// Tells every peer the local player is leaving.
+    pub disconnect: DisconnectHandle,
+    /// Clock estimates of the peers, updated whenever a pong arrives.
+    pub clock: Receiver<ClockSample>,
+    /// The local simulation tick, reported to peers in pongs.
+    pub local_tick: Arc<AtomicU32>,
+    /// Set by the app in host-authoritative mode, where only the host speaks
+    /// for players: the host then drops state sent by joiners.
+    pub host_authoritative: Arc<AtomicBool>,
+    /// Traffic and latency of every peer.
+    pub stats: ConnectionStats,
+}
+
+#[derive(Debug, Clone)]
+pub enum SessionEvent {
+    /// A peer speaks a different protocol version; its packets are dropped.
+    ProtocolMismatch { peer: SocketAddr, version: u8 },
+    /// The player `oid` announced it is leaving, either directly or relayed
+    /// by the host.
+    PeerLeft { peer: SocketAddr, oid: String },
+}
+
+/// Sends disconnect messages straight from the calling thread, so they go
+/// out even when the app is about to exit.
+pub struct DisconnectHandle {
+    socket: UdpSocket,
+    peers: Peers,
+}
+
+impl DisconnectHandle {
+    /// Datagrams may be lost, so the message is repeated a few times.
+    const REPEAT: usize = 3;
+
+    pub fn send(&self, oid: &str) -> Result<(), String> {
+        let bytes = NetMessage::Disconnect {
+            oid: oid.to_string(),
+        }
+        .encode()
+        .map_err(|e| format!("Failed to encode disconnect: {}", e))?;
+
+        for _ in 0..Self::REPEAT {
+            for peer in self.peers.lock().unwrap().iter() {
+                let _ = self.socket.send_to(&bytes, peer);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl HostSession {
+    pub fn new(socket: UdpSocket, peers: &[PeerInfo]) -> Result<Self, String> {
+        let mut addrs = Vec::with_capacity(peers.len());
+
+        for peer in peers {
+            let addr = (peer.host.as_str(), peer.port)
+                .to_socket_addrs()
+                .map_err(|e| format!("Failed to resolve relay {}:{}: {}", peer.host, peer.port, e))?
+                .next()
+                .ok_or_else(|| format!("No address for relay {}:{}", peer.host, peer.port))?;
+            addrs.push(addr);
+        }
+
+        Ok(Self {
+            socket,
+            peers: addrs,
+            paths: peers.iter().map(|peer| peer.path).collect(),
+            oids: peers.iter().map(|peer| peer.oid.clone()).collect(),
+        })
+    }
+
+    pub fn peers(&self) -> &[SocketAddr] {
+        &self.peers
+    }
+
+    /// Spawns the send and receive threads.
+    pub fn start(self) -> SessionChannels {
+        let (local_tx, local_rx) = crossbeam_channel::bounded::<GameState>(100);
+        let (remote_tx, remote_rx) = crossbeam_channel::bounded::<GameState>(100);
+        let (message_tx, outgoing_rx) = crossbeam_channel::bounded::<ChannelMessage>(100);
+        let (incoming_tx, message_rx) = crossbeam_channel::bounded::<ChannelMessage>(100);
+        let (event_tx, event_rx) = crossbeam_channel::unbounded::<SessionEvent>();
+        let (input_tx, outgoing_inputs) = crossbeam_channel::bounded::<InputCommand>(100);
+        let (incoming_inputs, inputs) = crossbeam_channel::bounded::<InputCommand>(100);
+        let (authoritative_tx, outgoing_authoritative) =
+            crossbeam_channel::bounded::<AuthoritativeState>(100);
+        let (incoming_authoritative, authoritative_rx) =
+            crossbeam_channel::bounded::<AuthoritativeState>(100);
+        let (rollback_tx, outgoing_rollback) = crossbeam_channel::bounded::<RollbackInput>(100);
+        let (incoming_rollback, rollback_rx) = crossbeam_channel::bounded::<RollbackInput>(100);
+        let (clock_tx, clock_rx) = crossbeam_channel::bounded::<ClockSample>(100);
+        let local_tick = Arc::new(AtomicU32::new(0));
+        let host_authoritative = Arc::new(AtomicBool::new(false));
+        let stats = ConnectionStats::default();
+        for (peer, path) in self.peers.iter().zip(&self.paths) {
+            stats.set_path(*peer, *path);
+        }
+
+        let links: Links = Arc::new(Mutex::new(
+            self.peers
+                .iter()
+                .map(|peer| (*peer, ReliableLink::default()))
+                .collect(),
+        ));
+
+        let snapshots: Snapshots = Arc::new(Mutex::new(
+            self.peers
+                .iter()
+                .map(|peer| (*peer, SnapshotLink::default()))
+                .collect(),
+        ));
+
+        let players: HashMap<SocketAddr, String> = self
+            .peers
+            .iter()
+            .zip(self.oids)
+            .filter_map(|(peer, oid)| Some((*peer, oid?)))
+            .collect();
+        let span = info_span!("host_session", peers = self.peers.len());
+        let peers: Peers = Arc::new(Mutex::new(self.peers));
+
+        let disconnect = DisconnectHandle {
+            socket: self.socket.try_clone().expect("Failed to clone socket"),
+            peers: peers.clone(),
+        };
+
+        let send_socket = TrackedSocket {
+            socket: self.socket.try_clone().expect("Failed to clone socket"),
+            stats: stats.clone(),
+        };
+        let send_peers = peers.clone();
+        let send_links = links.clone();
+        let send_snapshots = snapshots.clone();
+        thread::spawn(move || {
+            loop {
+                select! {
+                    recv(local_rx) -> state => match state {
+                        Ok(state) => {
+                            broadcast_game_state(&send_socket, &send_snapshots, &state, None);
+                        }
+                        Err(_) => break,
+                    },
+                    recv(outgoing_rx) -> message => match message {
+                        Ok(message) => {
+                            send_message(&send_socket, &send_links, &message, None);
+                        }
+                        Err(_) => break,
+                    },
+                    recv(outgoing_inputs) -> command => match command {
+                        Ok(command) => {
+                            broadcast(&send_socket, &send_peers, &NetMessage::Input(command));
+                        }
+                        Err(_) => break,
+                    },
+                    recv(outgoing_authoritative) -> state => match state {
+                        Ok(state) => {
+                            let message = NetMessage::AuthoritativeState(state);
+                            broadcast(&send_socket, &send_peers, &message);
+                        }
+                        Err(_) => break,
+                    },
+                    recv(outgoing_rollback) -> input => match input {
+                        Ok(input) => {
+                            broadcast(&send_socket, &send_peers, &NetMessage::RollbackInput(input));
+                        }
+                        Err(_) => break,
+                    },
+                }
+            }
+        });
+
+        let socket = TrackedSocket {
+            socket: self.socket,
+            stats: stats.clone(),
+        };
+        let tick = local_tick.clone();
+        let authoritative = host_authoritative.clone();
+        thread::spawn(move || {
+            let _span = span.enter();
+            info!("Relaying between peers");
+
+            let mut buf = [0u8; MAX_DATAGRAM_SIZE];
+            let mut mismatched = HashSet::new();
+            let mut departed = HashSet::new();
+            let clock = SessionClock::new();
+            let mut clocks: HashMap<SocketAddr, PeerClock> = HashMap::new();
+            let mut last_ping: Option<Instant> = None;
+
+            loop {
+                resend_overdue(&socket, &links);
+
+                if last_ping.is_none_or(|at| at.elapsed() >= PING_INTERVAL) {
+                    let ping = clock.ping();
+                    for peer in peers.lock().unwrap().iter() {
+                        socket.stats.ping_sent(*peer, ping.sent_at);
+                    }
+                    broadcast(&socket, &peers, &NetMessage::Ping(ping));
+                    last_ping = Some(Instant::now());
+                }
+
+                let (len, addr) = match socket.socket.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    Err(_) => continue,
+                };
+
+                if !peers.lock().unwrap().contains(&addr) {
+                    continue;
+                }
+
+                socket.stats.received(addr, len);
+
+                // A joiner whose welcome got lost keeps saying hello after
+                // the host stopped waiting for players.
+                if parse_hello(&buf[..len]).is_some() {
+                    let _ = socket.send_to(WELCOME_PACKET, &addr);
+                    continue;
+                }
+
+                // A joiner only ever speaks for the OID it said hello with.
+                let bound = players.get(&addr);
+                let message = NetMessage::decode(&buf[..len]);
+                if let Ok(message) = &message
+                    && let Some(oid) = message.oid()
+                {
+                    if bound.is_some_and(|bound| bound != oid) {
+                        debug!(peer = %addr, oid = %oid, "Dropping packet claiming another player");
+                        continue;
+                    }
+                    socket.stats.route(oid, addr);
+                }
+
+                // Joiners never send authoritative states, and in
+                // host-authoritative mode no state of their own either.
+                let host_only = match &message {
+                    Ok(NetMessage::AuthoritativeState(_)) => true,
+                    Ok(NetMessage::GameState(_) | NetMessage::StateDelta(_)) => {
+                        authoritative.load(Ordering::Relaxed)
+                    }
+                    _ => false,
+                };
+                if bound.is_some() && host_only {
+                    debug!(peer = %addr, "Dropping state sent by a joiner");
+                    continue;
+                }
+
+                let state = match message {
+                    Ok(NetMessage::GameState(state)) => state,
+                    Ok(NetMessage::StateDelta(snapshot)) => {
+                        let ack = SnapshotAck {
+                            slot: snapshot.slot,
+                            sequence: snapshot.sequence,
+                        };
+                        let decoded = snapshots
+                            .lock()
+                            .unwrap()
+                            .get_mut(&addr)
+                            .and_then(|link| link.decode(snapshot));
+
+                        let Some(state) = decoded else {
+                            debug!(peer = %addr, "Dropping snapshot with an unknown baseline");
+                            continue;
+                        };
+                        if bound.is_some_and(|bound| *bound != state.oid) {
+                            debug!(peer = %addr, oid = %state.oid, "Dropping snapshot claiming another player");
+                            continue;
+                        }
+
+                        if let Ok(bytes) = NetMessage::SnapshotAck(ack).encode() {
+                            let _ = socket.send_to(&bytes, &addr);
+                        }
+                        socket.stats.route(&state.oid, addr);
+                        state
+                    }
+                    Ok(NetMessage::SnapshotAck(ack)) => {
+                        if let Some(link) = snapshots.lock().unwrap().get_mut(&addr) {
+                            link.acknowledge(ack);
+                        }
+                        continue;
+                    }
+                    Ok(NetMessage::Channel(packet)) => {
+                        let received = links
+                            .lock()
+                            .unwrap()
+                            .get_mut(&addr)
+                            .map(|link| link.receive(packet));
+
+                        let Some((ack, messages)) = received else {
+                            continue;
+                        };
+
+                        if let Some(ack) = ack {
+                            let _ = socket.send_to(&ack, &addr);
+                        }
+
+                        for message in messages {
+                            if let Some(oid) = bound
+                                && !speaks_for(&message, oid)
+                            {
+                                debug!(peer = %addr, oid = %oid, channel = message.channel.id, "Dropping message claiming another sender");
+                                continue;
+                            }
+
+                            send_message(&socket, &links, &message, Some(addr));
+
+                            if incoming_tx.send(message).is_err() {
+                                debug!("Receiver disconnected, stopping host session");
+                                return;
+                            }
+                        }
+                        continue;
+                    }
+                    Ok(NetMessage::Disconnect { oid }) => {
+                        // Only the first copy is relayed and reported.
+                        if !departed.insert(oid.clone()) {
+                            continue;
+                        }
+
+                        info!(peer = %addr, oid = %oid, "Peer left the session");
+
+                        // Nothing sent to a departed player will ever be
+                        // acked, so stop sending it anything.
+                        if players.get(&addr) == Some(&oid) {
+                            links.lock().unwrap().remove(&addr);
+                            snapshots.lock().unwrap().remove(&addr);
+                            peers.lock().unwrap().retain(|peer| *peer != addr);
+                        }
+                        for link in snapshots.lock().unwrap().values_mut() {
+                            link.remove(&oid);
+                        }
+
+                        for peer in peers.lock().unwrap().iter().filter(|peer| **peer != addr) {
+                            let _ = socket.send_to(&buf[..len], peer);
+                        }
+
+                        let _ = event_tx.send(SessionEvent::PeerLeft { peer: addr, oid });
+                        continue;
+                    }
+                    Ok(NetMessage::Input(command)) => {
+                        if incoming_inputs.send(command).is_err() {
+                            debug!("Receiver disconnected, stopping host session");
+                            break;
+                        }
+                        continue;
+                    }
+                    Ok(NetMessage::AuthoritativeState(state)) => {
+                        if remote_tx.send(state.state.clone()).is_err()
+                            || incoming_authoritative.send(state).is_err()
+                        {
+                            debug!("Receiver disconnected, stopping host session");
+                            break;
+                        }
+                        continue;
+                    }
+                    Ok(NetMessage::RollbackInput(input)) => {
+                        for peer in peers.lock().unwrap().iter().filter(|peer| **peer != addr) {
+                            let _ = socket.send_to(&buf[..len], peer);
+                        }
+
+                        if incoming_rollback.send(input).is_err() {
+                            debug!("Receiver disconnected, stopping host session");
+                            break;
+                        }
+                        continue;
+                    }
+                    Ok(NetMessage::Ping(ping)) => {
+                        let pong = clock.pong(ping, tick.load(Ordering::Relaxed));
+                        if let Ok(bytes) = NetMessage::Pong(pong).encode() {
+                            let _ = socket.send_to(&bytes, &addr);
+                        }
+                        continue;
+                    }
+                    Ok(NetMessage::Pong(pong)) => {
+                        let sample = clocks.entry(addr).or_default().receive(&clock, addr, pong);
+                        socket
+                            .stats
+                            .pong_received(addr, pong.ping_sent_at, sample.rtt);
+
+                        // Stale samples are superseded anyway, so a full
+                        // channel just drops this one.
+                        if let Err(TrySendError::Disconnected(_)) = clock_tx.try_send(sample) {
+                            debug!("Receiver disconnected, stopping host session");
+                            break;
+                        }
+                        continue;
+                    }
+                    Err(ProtocolError::NotOurs) => continue,
+                    Err(ProtocolError::VersionMismatch { found, .. }) => {
+                        if mismatched.insert(addr) {
+                            warn!(
+                                peer = %addr,
+                                version = found,
+                                "Peer uses another protocol version, ignoring its packets"
+                            );
+                            let _ = event_tx.send(SessionEvent::ProtocolMismatch {
+                                peer: addr,
+                                version: found,
+                            });
+                        }
+                        continue;
+                    }
+                    Err(e) => {
+                        debug!(peer = %addr, error = %e, "Dropping packet");
+                        continue;
+                    }
+                };
+
+                GameStatePacket(state.clone()).log_receive();
+
+                // Baselines differ per link, so relayed states are re-encoded.
+                broadcast_game_state(&socket, &snapshots, &state, Some(addr));
+
+                if remote_tx.send(state).is_err() {
+                    debug!("Receiver disconnected, stopping host session");
+                    break;
+                }
+            }
+        });
+
+        SessionChannels {
+            sync_tx: local_tx,
+            receiver: remote_rx,
+            message_tx,
+            message_rx,
+            input_tx,
+            inputs,
+            authoritative_tx,
+            authoritative_rx,
+            rollback_tx,
+            rollback_rx,
+            events: event_rx,
+            disconnect,
+            clock: clock_rx,
+            local_tick,
+            host_authoritative,
+            stats,
+        }
+    }
+}
+
+/// Whether a channel message from the player bound to `oid` may be relayed.
+/// Session control comes only from the host, and replication must name its
+/// real sender: a `ReplicationPacket` is serialized starting with that OID.
+fn speaks_for(message: &ChannelMessage, oid: &str) -> bool {
+    if message.channel == Channel::CONTROL {
+        return false;
+    }
+
+    message.channel != Channel::REPLICATION
+        || bincode::deserialize::<String>(&message.payload).is_ok_and(|from| from == oid)
+}
+
+/// Sends `message` to every peer except `except`, through each peer's link.
+fn send_message(
+    socket: &TrackedSocket,
+    links: &Links,
+    message: &ChannelMessage,
+    except: Option<SocketAddr>,
+) {
+    let mut links = links.lock().unwrap();
+
+    for (peer, link) in links.iter_mut() {
+        if Some(*peer) == except {
+            continue;
+        }
+
+        match link.encode(message.channel, message.payload.clone()) {
+            Ok(bytes) => {
+                let _ = socket.send_to(&bytes, peer);
+            }
+            Err(e) => warn!(peer = %peer, error = %e, "Failed to encode message"),
+        }
+    }
+}
+
+fn resend_overdue(socket: &TrackedSocket, links: &Links) {
+    let now = Instant::now();
+    let mut links = links.lock().unwrap();
+
+    for (peer, link) in links.iter_mut() {
+        let given_up = link.given_up();
+        for bytes in link.resend_due(now) {
+            let _ = socket.send_to(&bytes, peer);
+        }
+        if link.given_up() > given_up {
+            warn!(
+                peer = %peer,
+                messages = link.given_up() - given_up,
+                "Gave up on unacknowledged reliable messages"
+            );
+        }
+    }
+}
+
+/// Sends an unsequenced `message` to every peer.
+fn broadcast(socket: &TrackedSocket, peers: &Peers, message: &NetMessage) {
+    match message.encode() {
+        Ok(bytes) => {
+            for peer in peers.lock().unwrap().iter() {
+                let _ = socket.send_to(&bytes, peer);
+            }
+        }
+        Err(e) => warn!(error = %e, "Failed to encode message"),
+    }
+}
+
+/// Sends `state` to every peer except `except`, delta-encoded against what
+/// each peer has acknowledged.
+fn broadcast_game_state(
+    socket: &TrackedSocket,
+    snapshots: &Snapshots,
+    state: &GameState,
+    except: Option<SocketAddr>,
+) {
+    GameStatePacket(state.clone()).log_send();
+
+    let now = Instant::now();
+    let mut snapshots = snapshots.lock().unwrap();
+
+    for (peer, link) in snapshots.iter_mut() {
+        if Some(*peer) == except {
+            continue;
+        }
+
+        let message = match link.encode(state, now) {
+            Ok(Some(snapshot)) => NetMessage::StateDelta(snapshot),
+            Ok(None) => continue,
+            Err(e) => {
+                debug!(peer = %peer, oid = %state.oid, error = %e, "Sending full state instead");
+                NetMessage::GameState(state.clone())
+            }
+        };
+
+        match message.encode() {
+            Ok(bytes) => {
+                let _ = socket.send_to(&bytes, peer);
+            }
+            Err(e) => warn!(peer = %peer, error = %e, "Failed to encode snapshot"),
+        }
+    }
+}
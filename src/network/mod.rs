@@ -1,7 +1,22 @@
+pub mod clock;
+pub mod handshake;
+pub mod host_session;
 pub mod noray_client;
+pub mod noray_protocol;
 pub mod packet_handler;
+pub mod protocol;
+pub mod snapshot;
 
-pub use noray_client::{NorayConfig, RegistrationInfo, register_only};
+pub use clock::{ClockSample, PING_INTERVAL, PeerClock, SessionClock};
+pub use host_session::{DisconnectHandle, HostSession, SessionChannels, SessionEvent};
+pub use noray_client::{ConnectionPath, NorayConfig, PeerInfo, RegistrationInfo};
+pub use noray_protocol::{NorayError, NorayMessage};
 pub use packet_handler::{
-    GameState, GameStatePacket, register_udp_socket, send_game_state, start_udp_relay,
+    AuthoritativeState, Channel, ChannelMessage, ChannelPacket, ConnectionStats, DeliveryMode,
+    GameState, GameStatePacket, InputCommand, PeerStats, PlayerInput, ReliableLink, RollbackInput,
+    TimePing, TimePong,
+};
+pub use protocol::{NetMessage, PROTOCOL_VERSION, ProtocolError};
+pub use snapshot::{
+    IDLE_KEEPALIVE, SLOT_TIMEOUT, SlotsExhausted, SnapshotAck, SnapshotLink, StateDelta,
 };
@@ -1,6 +1,3 @@
-use std::io::{BufRead, BufReader, Write};
-use std::net::TcpStream;
-use std::sync::mpsc;
 use std::time::Duration;
 
 #[derive(Debug, Clone)]
@@ -8,6 +5,10 @@ pub struct NorayConfig {
     pub host: String,
     pub tcp_port: u16,
     pub udp_port: u16,
+    /// Try noray's `connect` NAT punch-through before falling back to the relay.
+    pub direct_connect: bool,
+    /// How long to keep punching before giving up on a direct connection.
+    pub punch_timeout: Duration,
 }
 
 impl Default for NorayConfig {
@@ -16,6 +17,8 @@ impl Default for NorayConfig {
             host: String::from("127.0.0.1"),
             tcp_port: 8890,
             udp_port: 8809,
+            direct_connect: false,
+            punch_timeout: Duration::from_secs(3),
         }
     }
 }
@@ -26,359 +29,22 @@ pub struct RegistrationInfo {
     pub pid: String,
 }
 
-#[derive(Debug)]
+/// How packets reach a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionPath {
+    /// Through a noray relay port.
+    Relay,
+    /// Straight to the peer's public address after NAT punch-through.
+    Direct,
+}
+
+#[derive(Debug, Clone)]
 pub struct PeerInfo {
     pub port: u16,
     pub host: String,
-}
-
-pub fn register_only(config: &NorayConfig) -> Result<(RegistrationInfo, TcpStream), String> {
-    let tcp_addr = format!("{}:{}", config.host, config.tcp_port);
-    println!("[TCP] Connecting to {}", tcp_addr);
-    let mut stream =
-        TcpStream::connect(&tcp_addr).map_err(|e| format!("Failed to connect: {}", e))?;
-
-    stream
-        .set_read_timeout(Some(Duration::from_secs(5)))
-        .map_err(|e| format!("Failed to set timeout: {}", e))?;
-
-    println!("[TCP] Sending register-host");
-    stream
-        .write_all(b"register-host\n")
-        .map_err(|e| format!("Failed to send: {}", e))?;
-
-    let stream_clone = stream
-        .try_clone()
-        .map_err(|e| format!("Failed to clone stream: {}", e))?;
-
-    let mut reader = BufReader::new(&stream);
-
-    let mut oid = None;
-    let mut pid = None;
-
-    for i in 0..10 {
-        let mut line = String::new();
-        match reader.read_line(&mut line) {
-            Ok(n) if n > 0 => {
-                let line = line.trim();
-                println!("[TCP] Received: {}", line);
-
-                if line.starts_with("set-oid ") {
-                    oid = Some(line[8..].to_string());
-                } else if line.starts_with("set-pid ") {
-                    pid = Some(line[8..].to_string());
-                }
-
-                if oid.is_some() && pid.is_some() {
-                    break;
-                }
-            }
-            Ok(_) => {
-                if i >= 9 {
-                    return Err("Timeout waiting for registration".to_string());
-                }
-            }
-            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                if i >= 9 {
-                    return Err("Timeout waiting for registration".to_string());
-                }
-                std::thread::sleep(Duration::from_millis(100));
-            }
-            Err(e) => {
-                return Err(format!("Read error: {}", e));
-            }
-        }
-    }
-
-    match (oid, pid) {
-        (Some(oid), Some(pid)) => Ok((RegistrationInfo { oid, pid }, stream_clone)),
-        _ => Err("Failed to receive oid/pid".to_string()),
-    }
-}
-
-pub fn wait_for_connection(mut stream: TcpStream, host: String) -> Result<(u16, String), String> {
-    println!("[TCP] Waiting for noray response on existing connection...");
-
-    stream
-        .set_read_timeout(Some(Duration::from_secs(60)))
-        .map_err(|e| format!("Failed to set timeout: {}", e))?;
-
-    let mut reader = BufReader::new(stream);
-
-    for _ in 0..600 {
-        let mut line = String::new();
-        match reader.read_line(&mut line) {
-            Ok(n) if n > 0 => {
-                let line = line.trim();
-                println!(
-                    "[TCP] Received raw: '{}' (bytes: {:?})",
-                    line,
-                    line.as_bytes()
-                );
-
-                if line.starts_with("connect-relay") {
-                    let port_str = line.trim_start_matches("connect-relay").trim();
-                    println!(
-                        "[DEBUG] Port string: '{}' (len={})",
-                        port_str,
-                        port_str.len()
-                    );
-                    match port_str.parse::<u16>() {
-                        Ok(port) => return Ok((port, host)),
-                        Err(_) => return Err(format!("Invalid port format: '{}'", port_str)),
-                    }
-                } else if line.starts_with("ERROR") {
-                    return Err(format!("Server error: {}", line));
-                }
-            }
-            Ok(_) => {
-                std::thread::sleep(Duration::from_millis(100));
-            }
-            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                std::thread::sleep(Duration::from_millis(100));
-            }
-            Err(e) => {
-                return Err(format!("Read error: {}", e));
-            }
-        }
-    }
-
-    Err("Timeout waiting for response".to_string())
-}
-
-pub fn accept_additional_connections(stream: TcpStream, host: String, tx: mpsc::Sender<PeerInfo>) {
-    println!("[TCP] Started accepting additional connections...");
-
-    stream
-        .set_read_timeout(Some(Duration::from_secs(60)))
-        .map_err(|e| println!("[TCP] Failed to set timeout: {}", e))
-        .ok();
-
-    let mut reader = BufReader::new(stream);
-
-    loop {
-        let mut line = String::new();
-        match reader.read_line(&mut line) {
-            Ok(n) if n > 0 => {
-                let line = line.trim();
-                println!("[TCP] Received: '{}'", line);
-
-                if line.starts_with("connect-relay") {
-                    let port_str = line.trim_start_matches("connect-relay").trim();
-                    match port_str.parse::<u16>() {
-                        Ok(port) => {
-                            println!("[TCP] New peer connected on port {}", port);
-                            if tx
-                                .send(PeerInfo {
-                                    port,
-                                    host: host.clone(),
-                                })
-                                .is_err()
-                            {
-                                println!("[TCP] Receiver disconnected, stopping");
-                                break;
-                            }
-                        }
-                        Err(_) => {
-                            println!("[TCP] Invalid port format: '{}'", port_str);
-                        }
-                    }
-                } else if line.starts_with("ERROR") {
-                    println!("[TCP] Server error: {}", line);
-                }
-            }
-            Ok(_) => {
-                std::thread::sleep(Duration::from_millis(100));
-            }
-            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                std::thread::sleep(Duration::from_millis(100));
-            }
-            Err(e) => {
-                println!("[TCP] Read error: {}, stopping", e);
-                break;
-            }
-        }
-    }
-}
-
-pub fn wait_for_connections(
-    stream: TcpStream,
-    host: String,
-    num_players: u32,
-) -> Result<Vec<PeerInfo>, String> {
-    println!("[TCP] Waiting for {} players...", num_players);
-
-    stream
-        .set_read_timeout(Some(Duration::from_secs(300)))
-        .map_err(|e| format!("Failed to set timeout: {}", e))?;
-
-    let mut reader = BufReader::new(stream);
-    let mut peers = Vec::new();
-
-    while peers.len() < num_players as usize {
-        let mut line = String::new();
-        match reader.read_line(&mut line) {
-            Ok(n) if n > 0 => {
-                let line = line.trim();
-                println!("[TCP] Received: '{}'", line);
-
-                if line.starts_with("connect-relay") {
-                    let port_str = line.trim_start_matches("connect-relay").trim();
-                    match port_str.parse::<u16>() {
-                        Ok(port) => {
-                            let peer = PeerInfo {
-                                port,
-                                host: host.clone(),
-                            };
-                            println!(
-                                "[TCP] Player {} connected on port {}",
-                                peers.len() + 1,
-                                port
-                            );
-                            peers.push(peer);
-                        }
-                        Err(_) => {
-                            println!("[TCP] Invalid port format: '{}'", port_str);
-                        }
-                    }
-                } else if line.starts_with("ERROR") {
-                    return Err(format!("Server error: {}", line));
-                }
-            }
-            Ok(_) => {
-                std::thread::sleep(Duration::from_millis(100));
-            }
-            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                std::thread::sleep(Duration::from_millis(100));
-            }
-            Err(e) => {
-                return Err(format!("Read error: {}", e));
-            }
-        }
-    }
-
-    Ok(peers)
-}
-
-pub fn connect_to_relay(config: &NorayConfig, host_oid: &str) -> Result<(u16, String), String> {
-    let tcp_addr = format!("{}:{}", config.host, config.tcp_port);
-    println!("[TCP] Connecting to {}", tcp_addr);
-
-    let mut stream =
-        TcpStream::connect(&tcp_addr).map_err(|e| format!("Failed to connect: {}", e))?;
-
-    stream
-        .set_read_timeout(Some(Duration::from_secs(15)))
-        .map_err(|e| format!("Failed to set timeout: {}", e))?;
-
-    let cmd = format!("connect-relay {}\n", host_oid);
-    println!("[TCP] Sending: {}", cmd.trim());
-    stream
-        .write_all(cmd.as_bytes())
-        .map_err(|e| format!("Failed to send: {}", e))?;
-
-    let mut reader = BufReader::new(&stream);
-
-    println!("[TCP] Waiting for noray response...");
-
-    for i in 0..150 {
-        let mut line = String::new();
-        match reader.read_line(&mut line) {
-            Ok(n) if n > 0 => {
-                let line = line.trim();
-                println!(
-                    "[TCP] Received raw: '{}' (bytes: {:?})",
-                    line,
-                    line.as_bytes()
-                );
-
-                if line.starts_with("connect-relay") {
-                    let port_str = line.trim_start_matches("connect-relay").trim();
-                    println!(
-                        "[DEBUG] Port string: '{}' (len={})",
-                        port_str,
-                        port_str.len()
-                    );
-                    match port_str.parse::<u16>() {
-                        Ok(port) => return Ok((port, config.host.clone())),
-                        Err(_) => return Err(format!("Invalid port format: '{}'", port_str)),
-                    }
-                } else if line.starts_with("ERROR") || line.contains("Unknown") {
-                    return Err(format!("Server error: {}", line));
-                }
-            }
-            Ok(_) => {
-                std::thread::sleep(Duration::from_millis(100));
-            }
-            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                std::thread::sleep(Duration::from_millis(100));
-            }
-            Err(e) => {
-                return Err(format!("Read error: {}", e));
-            }
-        }
-    }
-
-    Err("Timeout waiting for response".to_string())
-}
-
-pub fn connect_to_relay_with_stream(
-    mut stream: TcpStream,
-    host_oid: &str,
-) -> Result<(u16, String), String> {
-    println!("[TCP] Using existing connection for connect-relay");
-
-    stream
-        .set_read_timeout(Some(Duration::from_secs(15)))
-        .map_err(|e| format!("Failed to set timeout: {}", e))?;
-
-    let cmd = format!("connect-relay {}\n", host_oid);
-    println!("[TCP] Sending: {}", cmd.trim());
-    stream
-        .write_all(cmd.as_bytes())
-        .map_err(|e| format!("Failed to send: {}", e))?;
-
-    let mut reader = BufReader::new(&stream);
-
-    println!("[TCP] Waiting for noray response...");
-
-    for _i in 0..150 {
-        let mut line = String::new();
-        match reader.read_line(&mut line) {
-            Ok(n) if n > 0 => {
-                let line = line.trim();
-                println!(
-                    "[TCP] Received raw: '{}' (bytes: {:?})",
-                    line,
-                    line.as_bytes()
-                );
-
-                if line.starts_with("connect-relay") {
-                    let port_str = line.trim_start_matches("connect-relay").trim();
-                    println!(
-                        "[DEBUG] Port string: '{}' (len={})",
-                        port_str,
-                        port_str.len()
-                    );
-                    match port_str.parse::<u16>() {
-                        Ok(port) => return Ok((port, "127.0.0.1".to_string())),
-                        Err(_) => return Err(format!("Invalid port format: '{}'", port_str)),
-                    }
-                } else if line.starts_with("ERROR") || line.contains("Unknown") {
-                    return Err(format!("Server error: {}", line));
-                }
-            }
-            Ok(_) => {
-                std::thread::sleep(Duration::from_millis(100));
-            }
-            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                std::thread::sleep(Duration::from_millis(100));
-            }
-            Err(e) => {
-                return Err(format!("Read error: {}", e));
-            }
-        }
-    }
-
-    Err("Timeout waiting for response".to_string())
+    pub path: ConnectionPath,
+    /// The player behind this peer, when every packet from it is that
+    /// player's own: set for a host's joiners, learned from their hello. A
+    /// joiner's host also relays other players, so it has none.
+    pub oid: Option<String>,
 }
@@ -0,0 +1,230 @@
+use std::fmt;
+use std::net::SocketAddr;
+
+/// A single line of noray's trimsock protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NorayMessage {
+    SetOid(String),
+    SetPid(String),
+    /// Public address of the other party, for NAT punch-through.
+    Connect(SocketAddr),
+    /// Relay port allocated for the other party.
+    ConnectRelay(u16),
+    /// Noray reports failures under the name of the failed command.
+    Error {
+        command: String,
+        message: String,
+    },
+    Unknown(String),
+}
+
+impl NorayMessage {
+    pub fn parse(line: &str) -> Result<Self, NorayError> {
+        let line = line.trim();
+        let (name, data) = line.split_once(' ').unwrap_or((line, ""));
+        let data = data.trim();
+
+        let message = match name {
+            "set-oid" => Self::SetOid(data.to_string()),
+            "set-pid" => Self::SetPid(data.to_string()),
+            "connect" => match parse_address(data) {
+                Some(address) => Self::Connect(address),
+                None if looks_like_address(data) => {
+                    return Err(NorayError::MalformedAddress(data.to_string()));
+                }
+                None => Self::error(name, data),
+            },
+            "connect-relay" => match data.parse::<u16>() {
+                Ok(port) => Self::ConnectRelay(port),
+                Err(_) if looks_like_port(data) => {
+                    return Err(NorayError::MalformedPort(data.to_string()));
+                }
+                Err(_) => Self::error(name, data),
+            },
+            _ if name.starts_with("ERROR") => Self::error("", data),
+            _ => Self::Unknown(line.to_string()),
+        };
+
+        Ok(message)
+    }
+
+    fn error(command: &str, message: &str) -> Self {
+        Self::Error {
+            command: command.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    /// Turns `Error` messages into a [`NorayError`], passing others through.
+    pub fn into_result(self) -> Result<Self, NorayError> {
+        match self {
+            Self::Error { message, .. } => Err(NorayError::from_server(&message)),
+            message => Ok(message),
+        }
+    }
+}
+
+/// Accepts both `1.2.3.4:5` and the unbracketed IPv6 form noray emits.
+fn parse_address(data: &str) -> Option<SocketAddr> {
+    data.parse().ok().or_else(|| {
+        let (host, port) = data.rsplit_once(':')?;
+        Some(SocketAddr::new(host.parse().ok()?, port.parse().ok()?))
+    })
+}
+
+fn looks_like_port(data: &str) -> bool {
+    !data.is_empty() && data.chars().all(|c| c.is_ascii_digit())
+}
+
+fn looks_like_address(data: &str) -> bool {
+    data.rsplit_once(':')
+        .is_some_and(|(host, port)| !host.contains(' ') && looks_like_port(port))
+}
+
+#[derive(Debug)]
+pub enum NorayError {
+    Io(std::io::Error),
+    ConnectionClosed,
+    /// Timed out waiting for the described response.
+    Timeout(&'static str),
+    UnknownOid(String),
+    MalformedPort(String),
+    MalformedAddress(String),
+    MissingRegistration,
+    Server(String),
+    Udp(String),
+}
+
+impl NorayError {
+    pub fn from_server(message: &str) -> Self {
+        const UNKNOWN_OID: &str = "Unknown host oid: ";
+
+        match message.find(UNKNOWN_OID) {
+            Some(index) => {
+                Self::UnknownOid(message[index + UNKNOWN_OID.len()..].trim().to_string())
+            }
+            None => Self::Server(message.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for NorayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::ConnectionClosed => write!(f, "Connection closed by noray"),
+            Self::Timeout(what) => write!(f, "Timeout waiting for {}", what),
+            Self::UnknownOid(oid) => write!(f, "Unknown host OID: {}", oid),
+            Self::MalformedPort(port) => write!(f, "Invalid port format: '{}'", port),
+            Self::MalformedAddress(address) => write!(f, "Invalid address format: '{}'", address),
+            Self::MissingRegistration => write!(f, "Failed to receive oid/pid"),
+            Self::Server(message) => write!(f, "Server error: {}", message),
+            Self::Udp(message) => write!(f, "UDP error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for NorayError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for NorayError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> NorayMessage {
+        NorayMessage::parse(line).unwrap()
+    }
+
+    #[test]
+    fn registration_ids_are_parsed() {
+        assert_eq!(
+            parse("set-oid abc\n"),
+            NorayMessage::SetOid("abc".to_string())
+        );
+        assert_eq!(
+            parse("set-pid  def "),
+            NorayMessage::SetPid("def".to_string())
+        );
+    }
+
+    #[test]
+    fn connect_accepts_ipv4_and_unbracketed_ipv6() {
+        assert_eq!(
+            parse("connect 1.2.3.4:5"),
+            NorayMessage::Connect("1.2.3.4:5".parse().unwrap())
+        );
+        assert_eq!(
+            parse("connect ::ffff:1.2.3.4:5"),
+            NorayMessage::Connect("[::ffff:1.2.3.4]:5".parse().unwrap())
+        );
+        assert_eq!(
+            parse("connect 2001:db8::1:49152"),
+            NorayMessage::Connect("[2001:db8::1]:49152".parse().unwrap())
+        );
+        assert!(matches!(
+            NorayMessage::parse("connect 1.2.3.4:70000"),
+            Err(NorayError::MalformedAddress(address)) if address == "1.2.3.4:70000"
+        ));
+    }
+
+    #[test]
+    fn connect_relay_rejects_malformed_ports() {
+        assert_eq!(
+            parse("connect-relay 49152"),
+            NorayMessage::ConnectRelay(49152)
+        );
+        assert!(matches!(
+            NorayMessage::parse("connect-relay 70000"),
+            Err(NorayError::MalformedPort(port)) if port == "70000"
+        ));
+    }
+
+    #[test]
+    fn server_errors_are_reported_under_the_failed_command() {
+        let message = parse("connect-relay Unknown host oid: abc");
+        assert_eq!(
+            message,
+            NorayMessage::Error {
+                command: "connect-relay".to_string(),
+                message: "Unknown host oid: abc".to_string(),
+            }
+        );
+        assert!(matches!(
+            message.into_result(),
+            Err(NorayError::UnknownOid(oid)) if oid == "abc"
+        ));
+
+        assert_eq!(
+            parse("connect Host unreachable"),
+            NorayMessage::Error {
+                command: "connect".to_string(),
+                message: "Host unreachable".to_string(),
+            }
+        );
+        assert!(matches!(
+            parse("ERROR something broke").into_result(),
+            Err(NorayError::Server(message)) if message == "something broke"
+        ));
+    }
+
+    #[test]
+    fn unknown_commands_are_kept_whole() {
+        assert_eq!(
+            parse(" shrug some data "),
+            NorayMessage::Unknown("shrug some data".to_string())
+        );
+        assert_eq!(parse(""), NorayMessage::Unknown(String::new()));
+    }
+}
@@ -1,12 +1,12 @@
-use bincode::{deserialize, serialize};
-use crossbeam_channel;
 use serde::{Deserialize, Serialize};
-use std::net::UdpSocket;
-use std::thread;
-use std::time::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::trace;
 
-const OID_LENGTH: usize = 32;
-const PACKET_SIZE: usize = 4 + OID_LENGTH + 4 + 4 + 4 + 4 + 1;
+use super::noray_client::ConnectionPath;
+use super::protocol::{NetMessage, ProtocolError};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameState {
@@ -19,130 +19,551 @@ pub struct GameState {
     pub is_jumping: bool,
 }
 
+/// Movement input for one simulation step of a player.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlayerInput {
+    /// -1 for left, 1 for right, 0 for standing still.
+    pub direction: i8,
+    pub jump: bool,
+    /// Length of the step in seconds.
+    pub dt: f32,
+}
+
+/// A joiner's input, numbered so the host can acknowledge it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputCommand {
+    pub oid: String,
+    pub sequence: u32,
+    pub input: PlayerInput,
+}
+
+/// The host's simulated state of a player, after applying all of its inputs
+/// up to `last_input`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthoritativeState {
+    pub last_input: u32,
+    pub state: GameState,
+}
+
+/// A peer's inputs for consecutive fixed frames, starting at `first_frame`,
+/// in rollback mode. Recent frames are repeated in every message so a lost
+/// datagram does not lose input.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RollbackInput {
+    pub oid: String,
+    pub first_frame: u32,
+    pub inputs: Vec<PlayerInput>,
+}
+
+/// Clock probe; the receiver answers with a [`TimePong`].
+///
+/// Times are microseconds on the sender's session clock.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TimePing {
+    pub sent_at: u64,
+}
+
+/// Answer to a [`TimePing`], carrying the responder's clock and tick.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TimePong {
+    /// `sent_at` of the ping being answered, on the pinger's clock.
+    pub ping_sent_at: u64,
+    /// When the ping was answered, on the responder's clock.
+    pub replied_at: u64,
+    /// The responder's simulation tick when it answered.
+    pub tick: u32,
+}
+
 pub struct GameStatePacket(pub GameState);
 
 impl GameStatePacket {
     pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
-        serialize(&self.0).map_err(|e| format!("Failed to serialize: {}", e))
+        NetMessage::GameState(self.0.clone())
+            .encode()
+            .map_err(|e| format!("Failed to serialize: {}", e))
     }
 
     pub fn log_send(&self) {
         let g = &self.0;
-        println!(
-            "[SEND] oid={} frame={} pos=({:.1},{:.1}) vel=({:.1},{:.1}) jump={}",
-            g.oid, g.frame, g.x, g.y, g.vx, g.vy, g.is_jumping as u8
+        trace!(
+            oid = %g.oid,
+            frame = g.frame,
+            x = g.x,
+            y = g.y,
+            vx = g.vx,
+            vy = g.vy,
+            jumping = g.is_jumping,
+            "Sending state"
         );
     }
 
     pub fn log_receive(&self) {
         let g = &self.0;
-        println!(
-            "[RECV] oid={} frame={} pos=({:.1},{:.1}) vel=({:.1},{:.1}) jump={}",
-            g.oid, g.frame, g.x, g.y, g.vx, g.vy, g.is_jumping as u8
+        trace!(
+            oid = %g.oid,
+            frame = g.frame,
+            x = g.x,
+            y = g.y,
+            vx = g.vx,
+            vy = g.vy,
+            jumping = g.is_jumping,
+            "Received state"
         );
     }
 }
 
-pub fn register_udp_socket(
-    config: &crate::network::NorayConfig,
-    pid: &str,
-) -> Result<UdpSocket, String> {
-    let socket =
-        UdpSocket::bind("0.0.0.0:0").map_err(|e| format!("Failed to bind UDP socket: {}", e))?;
+const RESEND_INTERVAL: Duration = Duration::from_millis(100);
+/// A reliable message still unacknowledged after this long is given up on,
+/// and the peer is told to stop waiting for it with a
+/// [`ChannelPacket::Skip`].
+const RELIABLE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Out-of-order reliable messages held per channel while waiting for a
+/// missing one. Further messages are dropped unacknowledged, so the sender
+/// retransmits them once the gap is filled.
+const MAX_BUFFERED: usize = 256;
+
+/// Delivery guarantee of a [`Channel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DeliveryMode {
+    /// Fire and forget; messages may be lost, duplicated or reordered.
+    Unreliable,
+    /// May be lost, but anything older than the newest received message is dropped.
+    UnreliableSequenced,
+    /// Acknowledged and retransmitted until received, delivered in send order.
+    ReliableOrdered,
+}
 
-    socket
-        .set_read_timeout(Some(Duration::from_millis(100)))
-        .map_err(|e| format!("Failed to set UDP timeout: {}", e))?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Channel {
+    pub id: u8,
+    pub mode: DeliveryMode,
+}
 
-    let udp_addr = format!("{}:{}", config.host, config.udp_port);
-    println!("Registering UDP at {}...", udp_addr);
+impl Channel {
+    pub const UNRELIABLE: Channel = Channel {
+        id: 0,
+        mode: DeliveryMode::Unreliable,
+    };
+    pub const SEQUENCED: Channel = Channel {
+        id: 1,
+        mode: DeliveryMode::UnreliableSequenced,
+    };
+    pub const RELIABLE: Channel = Channel {
+        id: 2,
+        mode: DeliveryMode::ReliableOrdered,
+    };
+    /// Reserved for component replication; never surfaced as
+    /// `MessageReceived`.
+    pub const REPLICATION: Channel = Channel {
+        id: u8::MAX,
+        mode: DeliveryMode::ReliableOrdered,
+    };
+    /// Reserved for session control, such as the host starting the game;
+    /// never surfaced as `MessageReceived`.
+    pub const CONTROL: Channel = Channel {
+        id: u8::MAX - 1,
+        mode: DeliveryMode::ReliableOrdered,
+    };
+}
 
-    socket
-        .send_to(pid.as_bytes(), &udp_addr)
-        .map_err(|e| format!("Failed to register UDP: {}", e))?;
+/// A message sent or received on a [`Channel`].
+#[derive(Debug, Clone)]
+pub struct ChannelMessage {
+    pub channel: Channel,
+    pub payload: Vec<u8>,
+}
 
-    let mut buf = [0u8; 1024];
-    match socket.recv_from(&mut buf) {
-        Ok((len, _)) => {
-            let response = String::from_utf8_lossy(&buf[..len]);
-            println!("UDP registration response: {}", response);
-        }
-        Err(_) => {
-            println!("No UDP registration response (continuing)...");
-        }
+/// Wire form of channel traffic, carried as [`NetMessage::Channel`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ChannelPacket {
+    Data {
+        channel: u8,
+        mode: DeliveryMode,
+        sequence: u16,
+        payload: Vec<u8>,
+    },
+    Ack {
+        channel: u8,
+        sequence: u16,
+    },
+    /// The sender gave up on the reliable messages before `sequence` that
+    /// were not acknowledged. Acked like the last of them.
+    Skip {
+        channel: u8,
+        sequence: u16,
+    },
+}
+
+impl ChannelPacket {
+    fn to_bytes(&self) -> Result<Vec<u8>, ProtocolError> {
+        NetMessage::Channel(self.clone()).encode()
     }
+}
+
+/// Returns true if `a` is newer than `b`, accounting for wrap-around.
+pub(super) fn sequence_newer(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000
+}
+
+struct PendingMessage {
+    bytes: Vec<u8>,
+    first_sent: Instant,
+    last_sent: Instant,
+}
 
-    Ok(socket)
+#[derive(Default)]
+struct SendChannel {
+    next_sequence: u16,
+    unacked: HashMap<u16, PendingMessage>,
+    /// Where the peer should skip to, until it acks the skip.
+    skip_to: Option<u16>,
+    skip_sent: Option<Instant>,
 }
 
-pub fn start_udp_relay(
-    socket: UdpSocket,
-    relay_port: u16,
-) -> crossbeam_channel::Receiver<GameState> {
-    println!(
-        "UDP relay listening for incoming packets on port {}",
-        relay_port
-    );
+#[derive(Default)]
+struct ReceiveChannel {
+    next_expected: u16,
+    newest: Option<u16>,
+    buffered: HashMap<u16, Vec<u8>>,
+}
+
+/// Per-peer sequencing, acknowledgement and retransmission state.
+#[derive(Default)]
+pub struct ReliableLink {
+    send: HashMap<u8, SendChannel>,
+    receive: HashMap<u8, ReceiveChannel>,
+    given_up: usize,
+}
+
+impl ReliableLink {
+    /// Frames `payload` for sending; reliable messages are kept for
+    /// retransmission until acknowledged.
+    pub fn encode(&mut self, channel: Channel, payload: Vec<u8>) -> Result<Vec<u8>, ProtocolError> {
+        let state = self.send.entry(channel.id).or_default();
+        let sequence = state.next_sequence;
+
+        let bytes = ChannelPacket::Data {
+            channel: channel.id,
+            mode: channel.mode,
+            sequence,
+            payload,
+        }
+        .to_bytes()?;
 
-    let (tx, rx) = crossbeam_channel::bounded::<GameState>(100);
+        // Only consume the sequence once the message can actually be sent, or
+        // a reliable receiver would wait forever for the gap.
+        state.next_sequence = sequence.wrapping_add(1);
+
+        if channel.mode == DeliveryMode::ReliableOrdered {
+            let now = Instant::now();
+            state.unacked.insert(
+                sequence,
+                PendingMessage {
+                    bytes: bytes.clone(),
+                    first_sent: now,
+                    last_sent: now,
+                },
+            );
+        }
+
+        Ok(bytes)
+    }
+
+    /// Handles an incoming channel packet, returning the ack to send back, if
+    /// any, and the messages now ready for delivery.
+    pub fn receive(&mut self, packet: ChannelPacket) -> (Option<Vec<u8>>, Vec<ChannelMessage>) {
+        match packet {
+            ChannelPacket::Ack { channel, sequence } => {
+                if let Some(state) = self.send.get_mut(&channel) {
+                    state.unacked.remove(&sequence);
+                    if state.skip_to == Some(sequence.wrapping_add(1)) {
+                        state.skip_to = None;
+                    }
+                }
+                (None, Vec::new())
+            }
+            ChannelPacket::Skip { channel, sequence } => {
+                let state = self.receive.entry(channel).or_default();
+                let mut delivered = Vec::new();
+
+                // Whatever arrived past the lost messages is delivered now.
+                while sequence_newer(sequence, state.next_expected) {
+                    if let Some(payload) = state.buffered.remove(&state.next_expected) {
+                        delivered.push(payload);
+                    }
+                    state.next_expected = state.next_expected.wrapping_add(1);
+                }
+                while let Some(payload) = state.buffered.remove(&state.next_expected) {
+                    delivered.push(payload);
+                    state.next_expected = state.next_expected.wrapping_add(1);
+                }
 
-    thread::spawn(move || {
-        let socket = socket;
-        println!("UDP relay listening for incoming packets");
+                let ack = ChannelPacket::Ack {
+                    channel,
+                    sequence: sequence.wrapping_sub(1),
+                }
+                .to_bytes()
+                .ok();
+                let channel = Channel {
+                    id: channel,
+                    mode: DeliveryMode::ReliableOrdered,
+                };
+                let delivered = delivered
+                    .into_iter()
+                    .map(|payload| ChannelMessage { channel, payload })
+                    .collect();
 
-        let mut buf = [0u8; PACKET_SIZE];
+                (ack, delivered)
+            }
+            ChannelPacket::Data {
+                channel,
+                mode,
+                sequence,
+                payload,
+            } => {
+                let state = self.receive.entry(channel).or_default();
+                let channel = Channel { id: channel, mode };
+                let mut delivered = Vec::new();
 
-        loop {
-            match socket.recv_from(&mut buf) {
-                Ok((len, _addr)) => {
-                    if len == PACKET_SIZE {
-                        match deserialize::<GameState>(&buf[..len]) {
-                            Ok(state) => {
-                                let packet = GameStatePacket(state.clone());
-                                packet.log_receive();
+                let ack = match mode {
+                    DeliveryMode::Unreliable => {
+                        delivered.push(payload);
+                        None
+                    }
+                    DeliveryMode::UnreliableSequenced => {
+                        if state
+                            .newest
+                            .is_none_or(|newest| sequence_newer(sequence, newest))
+                        {
+                            state.newest = Some(sequence);
+                            delivered.push(payload);
+                        }
+                        None
+                    }
+                    DeliveryMode::ReliableOrdered => {
+                        if sequence == state.next_expected {
+                            delivered.push(payload);
+                            state.next_expected = state.next_expected.wrapping_add(1);
 
-                                if tx.send(state).is_err() {
-                                    println!("Receiver disconnected, stopping UDP thread");
-                                    break;
-                                }
+                            while let Some(payload) = state.buffered.remove(&state.next_expected) {
+                                delivered.push(payload);
+                                state.next_expected = state.next_expected.wrapping_add(1);
                             }
-                            Err(e) => {
-                                println!("Failed to deserialize packet: {}", e);
+                        } else if sequence_newer(sequence, state.next_expected)
+                            && !state.buffered.contains_key(&sequence)
+                        {
+                            if state.buffered.len() >= MAX_BUFFERED {
+                                return (None, Vec::new());
                             }
+                            state.buffered.insert(sequence, payload);
+                        }
+
+                        // Duplicates are acked again in case the first ack was lost.
+                        ChannelPacket::Ack {
+                            channel: channel.id,
+                            sequence,
                         }
+                        .to_bytes()
+                        .ok()
                     }
+                };
+
+                let delivered = delivered
+                    .into_iter()
+                    .map(|payload| ChannelMessage { channel, payload })
+                    .collect();
+
+                (ack, delivered)
+            }
+        }
+    }
+
+    /// Returns reliable messages whose ack is overdue, marking them as resent.
+    /// Messages unacknowledged for longer than [`RELIABLE_TIMEOUT`] are
+    /// dropped instead, see [`ReliableLink::given_up`], and a
+    /// [`ChannelPacket::Skip`] past them is sent until acknowledged.
+    pub fn resend_due(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        let mut due = Vec::new();
+
+        for (channel, state) in self.send.iter_mut() {
+            let mut skip_to = state.skip_to;
+            state.unacked.retain(|sequence, pending| {
+                let expired = now.duration_since(pending.first_sent) >= RELIABLE_TIMEOUT;
+                let after = sequence.wrapping_add(1);
+                if expired && skip_to.is_none_or(|skip_to| sequence_newer(after, skip_to)) {
+                    skip_to = Some(after);
+                }
+                self.given_up += usize::from(expired);
+                !expired
+            });
+            if skip_to != state.skip_to {
+                state.skip_to = skip_to;
+                state.skip_sent = None;
+            }
+
+            for pending in state.unacked.values_mut() {
+                if now.duration_since(pending.last_sent) >= RESEND_INTERVAL {
+                    pending.last_sent = now;
+                    due.push(pending.bytes.clone());
                 }
-                Err(_) => {}
             }
+
+            if let Some(sequence) = state.skip_to
+                && state
+                    .skip_sent
+                    .is_none_or(|at| now.duration_since(at) >= RESEND_INTERVAL)
+                && let Ok(bytes) = (ChannelPacket::Skip {
+                    channel: *channel,
+                    sequence,
+                })
+                .to_bytes()
+            {
+                state.skip_sent = Some(now);
+                due.push(bytes);
+            }
+        }
+
+        due
+    }
+
+    /// Reliable messages dropped so far because they were never acknowledged.
+    pub fn given_up(&self) -> usize {
+        self.given_up
+    }
+}
+
+/// How long a ping may go unanswered before it counts as lost.
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Traffic and latency of one peer since the session started.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PeerStats {
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Pings answered within the timeout.
+    pub pings_answered: u64,
+    /// Pings never answered, the estimate of lost packets.
+    pub pings_lost: u64,
+    /// Round-trip time of the newest ping.
+    pub rtt: Duration,
+    /// Smoothed variation between consecutive round trips (RFC 3550).
+    pub jitter: Duration,
+    pub path: Option<ConnectionPath>,
+}
+
+impl PeerStats {
+    /// Fraction of pings lost, from 0 to 1.
+    pub fn loss(&self) -> f64 {
+        let total = self.pings_answered + self.pings_lost;
+        if total == 0 {
+            0.0
+        } else {
+            self.pings_lost as f64 / total as f64
         }
-    });
+    }
+}
 
-    rx
+#[derive(Default)]
+struct PeerTraffic {
+    stats: PeerStats,
+    /// `sent_at` and send time of pings awaiting their pong.
+    pending_pings: VecDeque<(u64, Instant)>,
+    has_rtt: bool,
 }
 
-pub fn send_game_state(
-    socket: &UdpSocket,
-    relay_addr: &str,
-    state: &GameState,
-) -> Result<(), String> {
-    let packet = GameStatePacket(state.clone());
-    packet.log_send();
+/// Per-peer [`PeerStats`], updated by the session threads as datagrams come
+/// and go.
+#[derive(Clone, Default)]
+pub struct ConnectionStats {
+    peers: Arc<Mutex<HashMap<SocketAddr, PeerTraffic>>>,
+    /// The peer each player's packets last arrived from.
+    routes: Arc<Mutex<HashMap<String, SocketAddr>>>,
+}
 
-    let bytes = packet
-        .to_bytes()
-        .map_err(|e| format!("Failed to encode packet: {}", e))?;
+impl ConnectionStats {
+    pub fn set_path(&self, peer: SocketAddr, path: ConnectionPath) {
+        let mut peers = self.peers.lock().unwrap();
+        peers.entry(peer).or_default().stats.path = Some(path);
+    }
+
+    /// Records that packets of player `oid` arrive from `peer`, directly or
+    /// relayed by the host.
+    pub fn route(&self, oid: &str, peer: SocketAddr) {
+        let mut routes = self.routes.lock().unwrap();
+        if routes.get(oid) != Some(&peer) {
+            routes.insert(oid.to_string(), peer);
+        }
+    }
 
-    if bytes.len() != PACKET_SIZE {
-        return Err(format!(
-            "Invalid packet size: {} (expected {})",
-            bytes.len(),
-            PACKET_SIZE
-        ));
+    pub fn routes(&self) -> HashMap<String, SocketAddr> {
+        self.routes.lock().unwrap().clone()
     }
 
-    socket
-        .send_to(&bytes, relay_addr)
-        .map_err(|e| format!("Failed to send packet: {}", e))?;
+    pub fn sent(&self, peer: SocketAddr, bytes: usize) {
+        let mut peers = self.peers.lock().unwrap();
+        let stats = &mut peers.entry(peer).or_default().stats;
+        stats.packets_sent += 1;
+        stats.bytes_sent += bytes as u64;
+    }
 
-    Ok(())
+    pub fn received(&self, peer: SocketAddr, bytes: usize) {
+        let mut peers = self.peers.lock().unwrap();
+        let stats = &mut peers.entry(peer).or_default().stats;
+        stats.packets_received += 1;
+        stats.bytes_received += bytes as u64;
+    }
+
+    /// Records a ping sent now, counting earlier pings that timed out as
+    /// lost.
+    pub fn ping_sent(&self, peer: SocketAddr, sent_at: u64) {
+        let now = Instant::now();
+        let mut peers = self.peers.lock().unwrap();
+        let traffic = peers.entry(peer).or_default();
+
+        while let Some((_, at)) = traffic.pending_pings.front()
+            && now.duration_since(*at) > PING_TIMEOUT
+        {
+            traffic.pending_pings.pop_front();
+            traffic.stats.pings_lost += 1;
+        }
+
+        traffic.pending_pings.push_back((sent_at, now));
+    }
+
+    /// Records the pong answering the ping sent at `ping_sent_at`. Pongs for
+    /// pings already counted as lost are ignored.
+    pub fn pong_received(&self, peer: SocketAddr, ping_sent_at: u64, rtt: Duration) {
+        let mut peers = self.peers.lock().unwrap();
+        let traffic = peers.entry(peer).or_default();
+
+        let Some(index) = traffic
+            .pending_pings
+            .iter()
+            .position(|(sent_at, _)| *sent_at == ping_sent_at)
+        else {
+            return;
+        };
+        traffic.pending_pings.remove(index);
+
+        let stats = &mut traffic.stats;
+        if traffic.has_rtt {
+            let delta = rtt.abs_diff(stats.rtt).as_secs_f64();
+            let jitter = stats.jitter.as_secs_f64();
+            stats.jitter = Duration::from_secs_f64(jitter + (delta - jitter) / 16.0);
+        }
+        stats.rtt = rtt;
+        stats.pings_answered += 1;
+        traffic.has_rtt = true;
+    }
+
+    pub fn snapshot(&self) -> HashMap<SocketAddr, PeerStats> {
+        self.peers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(peer, traffic)| (*peer, traffic.stats))
+            .collect()
+    }
 }
@@ -0,0 +1,270 @@
+use bincode::{Options, deserialize, serialize};
+use std::fmt;
+
+use super::packet_handler::{
+    AuthoritativeState, ChannelPacket, GameState, InputCommand, RollbackInput, TimePing, TimePong,
+};
+use super::snapshot::{SnapshotAck, StateDelta};
+
+/// Marks a datagram as ours; anything else on the socket (noray replies,
+/// punch packets) is ignored.
+pub const PROTOCOL_MAGIC: [u8; 2] = *b"NR";
+/// Bumped whenever the header or any message body changes incompatibly.
+pub const PROTOCOL_VERSION: u8 = 4;
+/// Magic (2) + version (1) + message type (1) + body length (2).
+pub const HEADER_SIZE: usize = 6;
+pub const MAX_DATAGRAM_SIZE: usize = 1500;
+
+/// Every message that travels over the session socket.
+///
+/// New kinds get a new variant and a new, never reused, type byte.
+#[derive(Debug, Clone)]
+pub enum NetMessage {
+    GameState(GameState),
+    Channel(ChannelPacket),
+    /// Sent by a peer that is leaving the session on purpose.
+    Disconnect {
+        oid: String,
+    },
+    /// Joiner input for the host to simulate, in host-authoritative mode.
+    Input(InputCommand),
+    /// Host-simulated state of a player, in host-authoritative mode.
+    AuthoritativeState(AuthoritativeState),
+    /// Per-frame inputs of a peer, in rollback mode.
+    RollbackInput(RollbackInput),
+    /// Clock probe between two directly connected peers; never relayed.
+    Ping(TimePing),
+    Pong(TimePong),
+    /// Delta-encoded player state, sent by sessions instead of `GameState`.
+    StateDelta(StateDelta),
+    SnapshotAck(SnapshotAck),
+}
+
+impl NetMessage {
+    fn message_type(&self) -> u8 {
+        match self {
+            Self::GameState(_) => 1,
+            Self::Channel(_) => 2,
+            Self::Disconnect { .. } => 3,
+            Self::Input(_) => 4,
+            Self::AuthoritativeState(_) => 5,
+            Self::RollbackInput(_) => 6,
+            Self::Ping(_) => 7,
+            Self::Pong(_) => 8,
+            Self::StateDelta(_) => 9,
+            Self::SnapshotAck(_) => 10,
+        }
+    }
+
+    /// The player the message is about, if any.
+    pub fn oid(&self) -> Option<&str> {
+        match self {
+            Self::GameState(state) => Some(&state.oid),
+            Self::Disconnect { oid } => Some(oid),
+            Self::Input(command) => Some(&command.oid),
+            Self::AuthoritativeState(state) => Some(&state.state.oid),
+            Self::RollbackInput(input) => Some(&input.oid),
+            Self::StateDelta(snapshot) => snapshot.oid.as_deref(),
+            Self::Channel(_) | Self::Ping(_) | Self::Pong(_) | Self::SnapshotAck(_) => None,
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, ProtocolError> {
+        let body = match self {
+            Self::GameState(state) => serialize(state),
+            Self::Channel(packet) => serialize(packet),
+            Self::Disconnect { oid } => serialize(oid),
+            Self::Input(command) => serialize(command),
+            Self::AuthoritativeState(state) => serialize(state),
+            Self::RollbackInput(input) => serialize(input),
+            Self::Ping(ping) => serialize(ping),
+            Self::Pong(pong) => serialize(pong),
+            Self::StateDelta(snapshot) => snapshot_codec().serialize(snapshot),
+            Self::SnapshotAck(ack) => serialize(ack),
+        }
+        .map_err(|e| ProtocolError::Malformed(e.to_string()))?;
+
+        if HEADER_SIZE + body.len() > MAX_DATAGRAM_SIZE {
+            return Err(ProtocolError::TooLarge(body.len()));
+        }
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + body.len());
+        bytes.extend_from_slice(&PROTOCOL_MAGIC);
+        bytes.push(PROTOCOL_VERSION);
+        bytes.push(self.message_type());
+        bytes.extend_from_slice(&(body.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&body);
+
+        Ok(bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        // Magic and version come first so they stay readable across versions.
+        if bytes.len() < 3 || bytes[..2] != PROTOCOL_MAGIC {
+            return Err(ProtocolError::NotOurs);
+        }
+
+        if bytes[2] != PROTOCOL_VERSION {
+            return Err(ProtocolError::VersionMismatch {
+                expected: PROTOCOL_VERSION,
+                found: bytes[2],
+            });
+        }
+
+        if bytes.len() < HEADER_SIZE {
+            return Err(ProtocolError::Truncated);
+        }
+
+        let message_type = bytes[3];
+        let length = u16::from_le_bytes([bytes[4], bytes[5]]) as usize;
+        let body = bytes
+            .get(HEADER_SIZE..HEADER_SIZE + length)
+            .ok_or(ProtocolError::Truncated)?;
+
+        let message = match message_type {
+            1 => deserialize(body).map(Self::GameState),
+            2 => deserialize(body).map(Self::Channel),
+            3 => deserialize(body).map(|oid| Self::Disconnect { oid }),
+            4 => deserialize(body).map(Self::Input),
+            5 => deserialize(body).map(Self::AuthoritativeState),
+            6 => deserialize(body).map(Self::RollbackInput),
+            7 => deserialize(body).map(Self::Ping),
+            8 => deserialize(body).map(Self::Pong),
+            9 => snapshot_codec().deserialize(body).map(Self::StateDelta),
+            10 => deserialize(body).map(Self::SnapshotAck),
+            other => return Err(ProtocolError::UnknownMessageType(other)),
+        };
+
+        message.map_err(|e| ProtocolError::Malformed(e.to_string()))
+    }
+}
+
+/// Snapshots use variable-length integers, so small sequence numbers and
+/// frame deltas take a byte each.
+fn snapshot_codec() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// Missing magic; not one of our datagrams.
+    NotOurs,
+    VersionMismatch {
+        expected: u8,
+        found: u8,
+    },
+    Truncated,
+    UnknownMessageType(u8),
+    Malformed(String),
+    TooLarge(usize),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotOurs => write!(f, "Not a bevy-noray packet"),
+            Self::VersionMismatch { expected, found } => write!(
+                f,
+                "Protocol version mismatch: expected {}, peer sent {}",
+                expected, found
+            ),
+            Self::Truncated => write!(f, "Truncated packet"),
+            Self::UnknownMessageType(message_type) => {
+                write!(f, "Unknown message type {}", message_type)
+            }
+            Self::Malformed(e) => write!(f, "Malformed packet: {}", e),
+            Self::TooLarge(len) => write!(f, "Message body of {} bytes is too large", len),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disconnect() -> NetMessage {
+        NetMessage::Disconnect {
+            oid: "player".to_string(),
+        }
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let state = GameState {
+            oid: "player".to_string(),
+            frame: 7,
+            x: 1.0,
+            y: 2.0,
+            vx: 3.0,
+            vy: 4.0,
+            is_jumping: true,
+        };
+        let bytes = NetMessage::GameState(state.clone()).encode().unwrap();
+        assert_eq!(bytes[..3], [b'N', b'R', PROTOCOL_VERSION]);
+
+        match NetMessage::decode(&bytes).unwrap() {
+            NetMessage::GameState(decoded) => {
+                assert_eq!((decoded.oid, decoded.frame), (state.oid, state.frame));
+                assert_eq!(
+                    [decoded.x, decoded.y, decoded.vx, decoded.vy],
+                    [1.0, 2.0, 3.0, 4.0]
+                );
+                assert!(decoded.is_jumping);
+            }
+            other => panic!("Decoded {:?}", other),
+        }
+        match NetMessage::decode(&disconnect().encode().unwrap()).unwrap() {
+            NetMessage::Disconnect { oid } => assert_eq!(oid, "player"),
+            other => panic!("Decoded {:?}", other),
+        }
+    }
+
+    #[test]
+    fn other_versions_are_reported() {
+        let mut bytes = disconnect().encode().unwrap();
+        bytes[2] = PROTOCOL_VERSION - 1;
+        assert_eq!(
+            NetMessage::decode(&bytes).unwrap_err(),
+            ProtocolError::VersionMismatch {
+                expected: PROTOCOL_VERSION,
+                found: PROTOCOL_VERSION - 1,
+            }
+        );
+        // Even when nothing else of the header made it.
+        assert!(matches!(
+            NetMessage::decode(&bytes[..3]),
+            Err(ProtocolError::VersionMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn short_packets_are_truncated() {
+        let bytes = disconnect().encode().unwrap();
+        assert_eq!(
+            NetMessage::decode(&bytes[..HEADER_SIZE - 1]).unwrap_err(),
+            ProtocolError::Truncated
+        );
+        assert_eq!(
+            NetMessage::decode(&bytes[..bytes.len() - 1]).unwrap_err(),
+            ProtocolError::Truncated
+        );
+    }
+
+    #[test]
+    fn packets_without_magic_are_not_ours() {
+        let mut bytes = disconnect().encode().unwrap();
+        for packet in [&b"noray-welcome"[..], b"NR", b""] {
+            assert_eq!(
+                NetMessage::decode(packet).unwrap_err(),
+                ProtocolError::NotOurs
+            );
+        }
+        bytes[0] = b'X';
+        assert_eq!(
+            NetMessage::decode(&bytes).unwrap_err(),
+            ProtocolError::NotOurs
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::packet_handler::{GameState, sequence_newer};
+
+/// Snapshots remembered per player on each end of a link. A baseline that
+/// has fallen out of this window is no longer used; a full snapshot is sent
+/// instead.
+const HISTORY: usize = 64;
+/// A player that stands still is still sent this often, so peers don't time
+/// it out.
+pub const IDLE_KEEPALIVE: Duration = Duration::from_secs(1);
+/// A player sent nothing for this long gives up its slot, so it can be
+/// reused. Far longer than [`IDLE_KEEPALIVE`].
+pub const SLOT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const X: u8 = 1 << 0;
+const Y: u8 = 1 << 1;
+const VX: u8 = 1 << 2;
+const VY: u8 = 1 << 3;
+const JUMPING: u8 = 1 << 4;
+const ALL_VALUES: u8 = X | Y | VX | VY;
+
+/// A player's [`GameState`] on the wire: either complete, or only the fields
+/// that changed since a baseline the receiver acknowledged.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateDelta {
+    /// Stands in for the player's OID on this link.
+    pub slot: u8,
+    pub sequence: u16,
+    /// Sequence of the snapshot this one is relative to; `None` for a full
+    /// snapshot.
+    pub baseline: Option<u16>,
+    /// Only sent in full snapshots.
+    pub oid: Option<String>,
+    /// Frames since the baseline, or the frame itself in a full snapshot.
+    pub frame: u32,
+    /// One bit per value in `values`, in `x, y, vx, vy` order, plus the
+    /// jumping flag.
+    pub fields: u8,
+    pub values: Vec<f32>,
+}
+
+impl StateDelta {
+    pub fn is_full(&self) -> bool {
+        self.baseline.is_none()
+    }
+}
+
+/// Tells the sender that the snapshot `sequence` of `slot` arrived, making
+/// it a usable baseline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotAck {
+    pub slot: u8,
+    pub sequence: u16,
+}
+
+/// Every slot of a link belongs to a player that is still being sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotsExhausted;
+
+impl fmt::Display for SlotsExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "All {} snapshot slots are in use", u8::MAX as usize + 1)
+    }
+}
+
+impl std::error::Error for SlotsExhausted {}
+
+#[derive(Debug, Default)]
+struct OutgoingSnapshots {
+    next_sequence: u16,
+    sent: VecDeque<(u16, GameState)>,
+    acked: Option<u16>,
+    last_sent: Option<Instant>,
+}
+
+/// Delta encoding state of one link, for the snapshots sent to the peer and
+/// those received from it.
+#[derive(Debug, Default)]
+pub struct SnapshotLink {
+    slots: HashMap<String, u8>,
+    outgoing: HashMap<u8, OutgoingSnapshots>,
+    received: HashMap<u8, VecDeque<(u16, GameState)>>,
+}
+
+impl SnapshotLink {
+    /// Encodes `state` against the newest acknowledged baseline of its
+    /// player. Returns `None` while the player is idle: unchanged since a
+    /// baseline the peer has and sent less than [`IDLE_KEEPALIVE`] ago.
+    ///
+    /// A new player takes the lowest free slot, after freeing those of
+    /// players not sent for [`SLOT_TIMEOUT`]; if none is left it fails.
+    pub fn encode(
+        &mut self,
+        state: &GameState,
+        now: Instant,
+    ) -> Result<Option<StateDelta>, SlotsExhausted> {
+        let slot = match self.slots.get(&state.oid) {
+            Some(slot) => *slot,
+            None => {
+                self.expire(now);
+                let slot = (0..=u8::MAX)
+                    .find(|slot| !self.outgoing.contains_key(slot))
+                    .ok_or(SlotsExhausted)?;
+                self.slots.insert(state.oid.clone(), slot);
+                slot
+            }
+        };
+        let outgoing = self.outgoing.entry(slot).or_default();
+
+        let baseline = outgoing.acked.and_then(|acked| {
+            outgoing
+                .sent
+                .iter()
+                .find(|(sequence, _)| *sequence == acked)
+                .cloned()
+        });
+
+        if let Some((_, base)) = &baseline
+            && outgoing
+                .sent
+                .back()
+                .is_some_and(|(_, newest)| same_motion(newest, state))
+            && same_motion(base, state)
+            && outgoing
+                .last_sent
+                .is_some_and(|at| now.duration_since(at) < IDLE_KEEPALIVE)
+        {
+            return Ok(None);
+        }
+
+        let sequence = outgoing.next_sequence;
+        outgoing.next_sequence = sequence.wrapping_add(1);
+        outgoing.sent.push_back((sequence, state.clone()));
+        while outgoing.sent.len() > HISTORY {
+            outgoing.sent.pop_front();
+        }
+        outgoing.last_sent = Some(now);
+
+        let snapshot = match baseline {
+            Some((baseline, base)) => {
+                let mut fields = 0;
+                let mut values = Vec::new();
+                let fields_and_values = [X, Y, VX, VY]
+                    .into_iter()
+                    .zip(motion(&base))
+                    .zip(motion(state));
+                for ((bit, old), new) in fields_and_values {
+                    if old.to_bits() != new.to_bits() {
+                        fields |= bit;
+                        values.push(new);
+                    }
+                }
+                StateDelta {
+                    slot,
+                    sequence,
+                    baseline: Some(baseline),
+                    oid: None,
+                    frame: state.frame.wrapping_sub(base.frame),
+                    fields: fields | jumping_bit(state),
+                    values,
+                }
+            }
+            None => StateDelta {
+                slot,
+                sequence,
+                baseline: None,
+                oid: Some(state.oid.clone()),
+                frame: state.frame,
+                fields: ALL_VALUES | jumping_bit(state),
+                values: motion(state).to_vec(),
+            },
+        };
+
+        Ok(Some(snapshot))
+    }
+
+    /// Frees the slots of `oid` in both directions, e.g. once it left.
+    pub fn remove(&mut self, oid: &str) {
+        if let Some(slot) = self.slots.remove(oid) {
+            self.outgoing.remove(&slot);
+        }
+        self.received
+            .retain(|_, history| history.back().is_none_or(|(_, state)| state.oid != oid));
+    }
+
+    /// Frees the outgoing slots of players not sent for [`SLOT_TIMEOUT`].
+    fn expire(&mut self, now: Instant) {
+        let outgoing = &mut self.outgoing;
+        self.slots.retain(|_, slot| {
+            let expired = outgoing[slot]
+                .last_sent
+                .is_none_or(|at| now.duration_since(at) >= SLOT_TIMEOUT);
+            if expired {
+                outgoing.remove(slot);
+            }
+            !expired
+        });
+    }
+
+    /// Records that the peer received `ack.sequence`, if it is newer than
+    /// the current baseline. Acks of snapshots no longer remembered, e.g.
+    /// sent to an earlier player of a reused slot, are ignored.
+    pub fn acknowledge(&mut self, ack: SnapshotAck) {
+        let Some(outgoing) = self.outgoing.get_mut(&ack.slot) else {
+            return;
+        };
+
+        if outgoing
+            .sent
+            .iter()
+            .any(|(sequence, _)| *sequence == ack.sequence)
+            && outgoing
+                .acked
+                .is_none_or(|acked| sequence_newer(ack.sequence, acked))
+        {
+            outgoing.acked = Some(ack.sequence);
+        }
+    }
+
+    /// Rebuilds the state in `snapshot`. Returns `None` if it is malformed
+    /// or its baseline is unknown here.
+    pub fn decode(&mut self, snapshot: StateDelta) -> Option<GameState> {
+        if snapshot.is_full() && snapshot.fields & ALL_VALUES != ALL_VALUES {
+            return None;
+        }
+        if snapshot.values.len() != (snapshot.fields & ALL_VALUES).count_ones() as usize {
+            return None;
+        }
+
+        let received = self.received.entry(snapshot.slot).or_default();
+
+        // The sender reused the slot for another player.
+        if let Some(oid) = &snapshot.oid
+            && received.back().is_some_and(|(_, state)| state.oid != *oid)
+        {
+            received.clear();
+        }
+
+        let mut state = match snapshot.baseline {
+            Some(baseline) => {
+                let (_, base) = received
+                    .iter()
+                    .find(|(sequence, _)| *sequence == baseline)?;
+                GameState {
+                    frame: base.frame.wrapping_add(snapshot.frame),
+                    ..base.clone()
+                }
+            }
+            None => GameState {
+                oid: snapshot.oid?,
+                frame: snapshot.frame,
+                x: 0.0,
+                y: 0.0,
+                vx: 0.0,
+                vy: 0.0,
+                is_jumping: false,
+            },
+        };
+
+        let mut values = snapshot.values.into_iter();
+        for (bit, field) in [
+            (X, &mut state.x),
+            (Y, &mut state.y),
+            (VX, &mut state.vx),
+            (VY, &mut state.vy),
+        ] {
+            if snapshot.fields & bit != 0 {
+                *field = values.next()?;
+            }
+        }
+        state.is_jumping = snapshot.fields & JUMPING != 0;
+
+        received.push_back((snapshot.sequence, state.clone()));
+        while received.len() > HISTORY {
+            received.pop_front();
+        }
+
+        Some(state)
+    }
+}
+
+fn motion(state: &GameState) -> [f32; 4] {
+    [state.x, state.y, state.vx, state.vy]
+}
+
+fn jumping_bit(state: &GameState) -> u8 {
+    if state.is_jumping { JUMPING } else { 0 }
+}
+
+/// Equal apart from the frame.
+fn same_motion(a: &GameState, b: &GameState) -> bool {
+    a.oid == b.oid
+        && motion(a).map(f32::to_bits) == motion(b).map(f32::to_bits)
+        && a.is_jumping == b.is_jumping
+}
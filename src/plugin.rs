@@ -0,0 +1,781 @@
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+
+use crate::game::local_input::{LocalInputLatch, clear_latched_jump, latch_local_input};
+use crate::game::player::{IsJumping, Velocity};
+use crate::local_player_data::LocalPlayerMarker;
+use crate::network::handshake::{self, HandshakeProgress, NoraySession};
+use crate::network::{
+    Channel, ChannelMessage, DisconnectHandle, GameState, NorayConfig, PROTOCOL_VERSION, PeerInfo,
+    SessionEvent,
+};
+use crate::sync::authoritative::{
+    AuthorityChannels, PendingInputs, reconcile_local_player, send_authoritative_states,
+    send_local_input, simulate_remote_inputs,
+};
+use crate::sync::authority::{
+    AuthorityChanged, RequestAuthority, return_departed_authority, send_authority_requests,
+};
+use crate::sync::network_entity::NetworkEntityMap;
+use crate::sync::network_event::{
+    NetworkEventPacket, NetworkEventRegistry, PendingNetworkEvents, is_targeted,
+};
+use crate::sync::network_time::{publish_local_tick, update_network_time};
+use crate::sync::replication::{
+    OutgoingReplication, PendingReplication, ReplicationPacket, ReplicationRegistry,
+    apply_replication, assign_network_ids, collect_spawned, despawn_departed_entities,
+    send_replication, track_despawns,
+};
+use crate::sync::rollback::{
+    RollbackChannels, RollbackConfig, RollbackSession, advance_rollback, has_local_player,
+};
+use crate::sync::stats::{register_network_diagnostics, update_network_stats};
+use crate::sync::{
+    ClockChannels, InterpolationConfig, NetworkStats, NetworkTime, PeerConnected, PeerDisconnected,
+    PeerTimeout, RemotePlayerData, RemoteUpdateReceiver, SessionStats,
+    despawn_disconnected_players, detect_disconnected_peers, receive_remote_updates,
+    update_remote_player_transforms,
+};
+
+/// Fixed simulation ticks per second unless set with
+/// [`NorayPlugin::with_tick_rate`].
+pub const DEFAULT_TICK_RATE: f64 = 60.0;
+
+/// Which side of a noray session this app plays. Inserted as a resource once
+/// the handshake starts.
+#[derive(Resource, Debug, Clone)]
+pub enum NorayRole {
+    /// Register with noray and wait until `players - 1` peers have joined.
+    Host { players: u32 },
+    /// Connect to the host registered under `host_oid`.
+    Join { host_oid: String },
+}
+
+impl NorayRole {
+    pub fn is_host(&self) -> bool {
+        matches!(self, Self::Host { .. })
+    }
+}
+
+/// Who decides where players are.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncMode {
+    /// Every peer simulates its own player and broadcasts the result.
+    #[default]
+    StateBroadcast,
+    /// Joiners send inputs; the host simulates every player and sends the
+    /// results back. Joiners predict their own player and reconcile it with
+    /// the host's state.
+    HostAuthoritative,
+    /// Every peer sends its input per fixed frame and simulates all players,
+    /// predicting late remote inputs and rolling back when they arrive. The
+    /// app's own movement systems must not run in this mode.
+    Rollback,
+}
+
+/// Runs the noray handshake for the given role in the background and wires
+/// the resulting UDP session into the app once it is established.
+pub struct NorayPlugin {
+    pub config: NorayConfig,
+    /// `None` starts in [`NorayState::Lobby`] and waits for a
+    /// [`StartHandshake`] event instead.
+    pub role: Option<NorayRole>,
+    pub sync_mode: SyncMode,
+    /// Fixed simulation ticks per second; every peer must use the same rate.
+    pub tick_rate: f64,
+}
+
+impl NorayPlugin {
+    pub fn host(config: NorayConfig, players: u32) -> Self {
+        Self {
+            config,
+            role: Some(NorayRole::Host { players }),
+            sync_mode: SyncMode::default(),
+            tick_rate: DEFAULT_TICK_RATE,
+        }
+    }
+
+    /// Hosts without playing, e.g. as a headless server: waits for `players`
+    /// joiners and never spawns a local player.
+    pub fn dedicated_host(config: NorayConfig, players: u32) -> Self {
+        Self::host(config, players + 1)
+    }
+
+    pub fn join(config: NorayConfig, host_oid: impl Into<String>) -> Self {
+        Self {
+            config,
+            role: Some(NorayRole::Join {
+                host_oid: host_oid.into(),
+            }),
+            sync_mode: SyncMode::default(),
+            tick_rate: DEFAULT_TICK_RATE,
+        }
+    }
+
+    /// Waits in [`NorayState::Lobby`] until the app picks a role with
+    /// [`StartHandshake`], e.g. from a lobby screen.
+    pub fn lobby(config: NorayConfig) -> Self {
+        Self {
+            config,
+            role: None,
+            sync_mode: SyncMode::default(),
+            tick_rate: DEFAULT_TICK_RATE,
+        }
+    }
+
+    pub fn with_sync_mode(mut self, sync_mode: SyncMode) -> Self {
+        self.sync_mode = sync_mode;
+        self
+    }
+
+    pub fn with_tick_rate(mut self, hz: f64) -> Self {
+        self.tick_rate = hz;
+        self
+    }
+}
+
+/// Progress of the noray handshake.
+#[derive(States, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum NorayState {
+    /// No role chosen yet; see [`NorayPlugin::lobby`].
+    Lobby,
+    #[default]
+    Registering,
+    WaitingForPeers,
+    Connecting,
+    /// The session is up but the game hasn't started. Joiners wait here for
+    /// the host; a host started from the lobby waits for [`StartGame`].
+    Ready,
+    InGame,
+    Failed,
+}
+
+/// Registers with noray in the given role. Only read in
+/// [`NorayState::Lobby`].
+#[derive(Event, Debug, Clone)]
+pub struct StartHandshake(pub NorayRole);
+
+/// Moves a host waiting in [`NorayState::Ready`] in game, taking the joiners
+/// along.
+#[derive(Event, Debug, Clone, Default)]
+pub struct StartGame;
+
+/// Session control sent by the host on [`Channel::CONTROL`].
+#[derive(Debug, Serialize, Deserialize)]
+enum ControlMessage {
+    /// The host entered the game; joiners waiting in [`NorayState::Ready`]
+    /// follow.
+    StartGame,
+}
+
+/// The noray settings kept for handshakes started after `build`.
+#[derive(Resource)]
+struct HandshakeSettings {
+    config: NorayConfig,
+    tick_rate: f64,
+}
+
+/// Present while a host started from the lobby should wait in
+/// [`NorayState::Ready`] for [`StartGame`].
+#[derive(Resource)]
+struct ManualStart;
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum NoraySet {
+    /// Drains incoming remote state and applies it to remote players.
+    Receive,
+    /// Publishes the local player's state to the network thread. Configured in
+    /// `FixedUpdate`, so game systems that move the local player should run
+    /// there before it.
+    Send,
+}
+
+#[derive(Resource, Clone)]
+pub struct PlayerRegistrationInfo {
+    pub oid: String,
+    pub pid: String,
+}
+
+#[derive(Resource, Default)]
+pub struct NetworkingState {
+    pub connected: bool,
+    pub error_message: String,
+    pub peers_joined: usize,
+    pub peers_expected: usize,
+    /// Joiners that reached the host so far. Only filled on the host.
+    pub peers: Vec<PeerInfo>,
+}
+
+/// The fixed tick being simulated, sent as [`GameState::frame`].
+///
+/// Counts from the host's first in-game tick: joiners follow the host's tick
+/// as estimated by [`NetworkTime`], so all peers agree on it.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SimulationTick(pub u32);
+
+/// How far a joiner's tick may drift from the host's before it is reset.
+const MAX_TICK_DRIFT: u32 = 2;
+
+#[derive(Resource)]
+pub struct SyncChannel(pub Sender<GameState>);
+
+/// Sends and receives [`ChannelMessage`]s, with the delivery guarantee of the
+/// chosen [`Channel`].
+#[derive(Resource)]
+pub struct NetworkMessages {
+    tx: Sender<ChannelMessage>,
+    rx: Receiver<ChannelMessage>,
+}
+
+impl NetworkMessages {
+    /// Queues `payload` for every peer on `channel`.
+    pub fn send(&self, channel: Channel, payload: Vec<u8>) {
+        let _ = self.tx.send(ChannelMessage { channel, payload });
+    }
+}
+
+/// A channel message received from a remote peer.
+#[derive(Event, Debug, Clone)]
+pub struct MessageReceived(pub ChannelMessage);
+
+/// Something notable happened on the running session, such as a peer
+/// speaking an incompatible protocol version.
+#[derive(Event, Debug, Clone)]
+pub struct SessionEventReceived(pub SessionEvent);
+
+#[derive(Resource)]
+pub struct SessionEvents(pub Receiver<SessionEvent>);
+
+#[derive(Resource)]
+pub struct SessionDisconnect(pub DisconnectHandle);
+
+/// Tokio runtime driving the background handshake.
+#[derive(Resource)]
+pub struct NorayRuntime(pub Runtime);
+
+/// Handle to the in-flight handshake task.
+///
+/// Removed once the handshake either connects or fails.
+#[derive(Resource)]
+pub struct NorayHandshake {
+    progress: Receiver<HandshakeProgress>,
+    task: JoinHandle<()>,
+}
+
+impl NorayHandshake {
+    /// Aborts the handshake; the app moves to [`NorayState::Failed`] on the
+    /// next update.
+    pub fn cancel(&self) {
+        self.task.abort();
+    }
+}
+
+impl Plugin for NorayPlugin {
+    fn build(&self, app: &mut App) {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("noray-handshake")
+            .enable_all()
+            .build()
+            .expect("Failed to start tokio runtime");
+
+        match &self.role {
+            Some(role) => {
+                app.insert_state(NorayState::Registering)
+                    .insert_resource(spawn_handshake(&runtime, self.config.clone(), role.clone()))
+                    .insert_resource(NetworkTime::new(self.tick_rate, role.is_host()))
+                    .insert_resource(role.clone());
+            }
+            None => {
+                app.insert_state(NorayState::Lobby)
+                    .insert_resource(NetworkTime::new(self.tick_rate, false));
+            }
+        }
+
+        app.add_event::<MessageReceived>()
+            .add_event::<SessionEventReceived>()
+            .add_event::<PeerConnected>()
+            .add_event::<PeerDisconnected>()
+            .add_event::<StartHandshake>()
+            .add_event::<StartGame>()
+            .add_event::<RequestAuthority>()
+            .add_event::<AuthorityChanged>()
+            .insert_resource(NorayRuntime(runtime))
+            .insert_resource(HandshakeSettings {
+                config: self.config.clone(),
+                tick_rate: self.tick_rate,
+            })
+            .insert_resource(NetworkingState::default())
+            .insert_resource(RemotePlayerData::default())
+            .insert_resource(self.sync_mode)
+            .init_resource::<InterpolationConfig>()
+            .init_resource::<PeerTimeout>()
+            .init_resource::<SimulationTick>()
+            .init_resource::<NetworkStats>()
+            .init_resource::<LocalInputLatch>()
+            .init_resource::<ReplicationRegistry>()
+            .init_resource::<OutgoingReplication>()
+            .init_resource::<PendingReplication>()
+            .init_resource::<NetworkEntityMap>()
+            .init_resource::<NetworkEventRegistry>()
+            .init_resource::<PendingNetworkEvents>()
+            .insert_resource(Time::<Fixed>::from_hz(self.tick_rate))
+            .configure_sets(
+                Update,
+                (
+                    NoraySet::Receive
+                        .run_if(in_state(NorayState::Ready).or_else(in_state(NorayState::InGame))),
+                    NoraySet::Send.run_if(in_state(NorayState::InGame)),
+                )
+                    .chain(),
+            )
+            .configure_sets(
+                FixedUpdate,
+                NoraySet::Send.run_if(in_state(NorayState::InGame)),
+            )
+            .add_systems(
+                PreUpdate,
+                (
+                    start_handshake.run_if(in_state(NorayState::Lobby)),
+                    poll_handshake,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                PreUpdate,
+                latch_local_input
+                    .after(InputSystem)
+                    .run_if(resource_exists::<ButtonInput<KeyCode>>),
+            )
+            .add_systems(FixedPostUpdate, clear_latched_jump)
+            .add_systems(
+                FixedFirst,
+                follow_host_tick
+                    .run_if(in_state(NorayState::InGame))
+                    .run_if(is_joiner),
+            )
+            .add_systems(
+                FixedLast,
+                (advance_simulation_tick, publish_local_tick)
+                    .chain()
+                    .run_if(in_state(NorayState::InGame)),
+            )
+            .add_systems(
+                Update,
+                (
+                    receive_remote_updates,
+                    update_remote_player_transforms,
+                    receive_channel_messages,
+                    receive_session_events,
+                    detect_disconnected_peers,
+                    despawn_disconnected_players,
+                    update_network_time,
+                    update_network_stats,
+                )
+                    .chain()
+                    .in_set(NoraySet::Receive),
+            )
+            .add_systems(
+                Update,
+                (
+                    (
+                        despawn_departed_entities,
+                        return_departed_authority,
+                        track_despawns,
+                        assign_network_ids,
+                        apply_replication,
+                    )
+                        .chain()
+                        .after(despawn_disconnected_players)
+                        .in_set(NoraySet::Receive),
+                    (collect_spawned, send_authority_requests, send_replication)
+                        .chain()
+                        .in_set(NoraySet::Send),
+                ),
+            )
+            .add_systems(
+                Update,
+                start_game
+                    .run_if(in_state(NorayState::Ready))
+                    .run_if(is_host),
+            )
+            .add_systems(
+                OnEnter(NorayState::InGame),
+                announce_game_start.run_if(is_host),
+            )
+            .add_systems(OnEnter(NorayState::Lobby), reset_handshake)
+            .add_systems(Last, send_disconnect_on_exit);
+
+        register_network_diagnostics(app);
+
+        match self.sync_mode {
+            SyncMode::StateBroadcast => {
+                app.add_systems(FixedUpdate, sync_local_state.in_set(NoraySet::Send));
+            }
+            SyncMode::HostAuthoritative => {
+                app.init_resource::<PendingInputs>()
+                    .add_systems(
+                        Update,
+                        reconcile_local_player
+                            .run_if(is_joiner)
+                            .after(receive_remote_updates)
+                            .in_set(NoraySet::Receive),
+                    )
+                    .add_systems(
+                        FixedUpdate,
+                        simulate_remote_inputs
+                            .run_if(in_state(NorayState::InGame))
+                            .run_if(is_host)
+                            .before(NoraySet::Send),
+                    )
+                    .add_systems(
+                        FixedUpdate,
+                        (
+                            (sync_local_state, send_authoritative_states).run_if(is_host),
+                            send_local_input.run_if(is_joiner),
+                        )
+                            .in_set(NoraySet::Send),
+                    );
+            }
+            SyncMode::Rollback => {
+                app.init_resource::<RollbackConfig>()
+                    .init_resource::<RollbackSession>()
+                    .add_systems(
+                        FixedUpdate,
+                        advance_rollback
+                            .run_if(in_state(NorayState::InGame))
+                            .run_if(has_local_player),
+                    );
+            }
+        }
+    }
+}
+
+fn is_host(role: Option<Res<NorayRole>>) -> bool {
+    role.is_some_and(|role| role.is_host())
+}
+
+fn is_joiner(role: Option<Res<NorayRole>>) -> bool {
+    role.is_some_and(|role| !role.is_host())
+}
+
+fn spawn_handshake(runtime: &Runtime, config: NorayConfig, role: NorayRole) -> NorayHandshake {
+    let (progress_tx, progress_rx) = crossbeam_channel::unbounded();
+    let task = runtime.spawn(run_handshake(config, role, progress_tx));
+
+    NorayHandshake {
+        progress: progress_rx,
+        task,
+    }
+}
+
+fn start_handshake(
+    mut commands: Commands,
+    mut events: EventReader<StartHandshake>,
+    runtime: Res<NorayRuntime>,
+    settings: Res<HandshakeSettings>,
+    mut next_state: ResMut<NextState<NorayState>>,
+) {
+    let Some(StartHandshake(role)) = events.read().last() else {
+        return;
+    };
+
+    info!(?role, "Starting noray handshake");
+    if role.is_host() {
+        commands.insert_resource(ManualStart);
+    }
+    commands.insert_resource(spawn_handshake(
+        &runtime.0,
+        settings.config.clone(),
+        role.clone(),
+    ));
+    commands.insert_resource(NetworkTime::new(settings.tick_rate, role.is_host()));
+    commands.insert_resource(role.clone());
+    next_state.set(NorayState::Registering);
+}
+
+/// Forgets a failed or cancelled attempt so the lobby can start another,
+/// closing its session if one was already up.
+fn reset_handshake(mut commands: Commands, mut networking: ResMut<NetworkingState>) {
+    commands.remove_resource::<NorayHandshake>();
+    commands.remove_resource::<NorayRole>();
+    commands.remove_resource::<ManualStart>();
+    commands.remove_resource::<PlayerRegistrationInfo>();
+    // Dropping the session's channels stops its threads.
+    commands.remove_resource::<RemoteUpdateReceiver>();
+    commands.remove_resource::<SyncChannel>();
+    commands.remove_resource::<NetworkMessages>();
+    commands.remove_resource::<AuthorityChannels>();
+    commands.remove_resource::<RollbackChannels>();
+    commands.remove_resource::<SessionEvents>();
+    commands.remove_resource::<SessionDisconnect>();
+    commands.remove_resource::<SessionStats>();
+    commands.remove_resource::<ClockChannels>();
+    commands.insert_resource(NetworkEntityMap::default());
+    commands.insert_resource(RemotePlayerData::default());
+    commands.insert_resource(SimulationTick::default());
+    *networking = NetworkingState::default();
+}
+
+fn start_game(mut events: EventReader<StartGame>, mut next_state: ResMut<NextState<NorayState>>) {
+    if events.read().last().is_some() {
+        next_state.set(NorayState::InGame);
+    }
+}
+
+/// Tells joiners waiting in [`NorayState::Ready`] that the game started.
+fn announce_game_start(messages: Option<Res<NetworkMessages>>) {
+    if let Some(messages) = messages
+        && let Ok(payload) = bincode::serialize(&ControlMessage::StartGame)
+    {
+        messages.send(Channel::CONTROL, payload);
+    }
+}
+
+async fn run_handshake(config: NorayConfig, role: NorayRole, progress: Sender<HandshakeProgress>) {
+    let result = match role {
+        NorayRole::Host { players } => handshake::host(config, players, progress.clone()).await,
+        NorayRole::Join { host_oid } => handshake::join(config, host_oid, progress.clone()).await,
+    };
+
+    let _ = match result {
+        Ok(session) => progress.send(HandshakeProgress::Connected(Box::new(session))),
+        Err(e) => progress.send(HandshakeProgress::Failed(e)),
+    };
+}
+
+fn poll_handshake(
+    mut commands: Commands,
+    handshake: Option<Res<NorayHandshake>>,
+    role: Option<Res<NorayRole>>,
+    manual_start: Option<Res<ManualStart>>,
+    sync_mode: Res<SyncMode>,
+    mut networking: ResMut<NetworkingState>,
+    mut next_state: ResMut<NextState<NorayState>>,
+) {
+    let Some(handshake) = handshake else {
+        return;
+    };
+
+    loop {
+        match handshake.progress.try_recv() {
+            Ok(HandshakeProgress::Registered(registration)) => {
+                commands.insert_resource(PlayerRegistrationInfo {
+                    oid: registration.oid,
+                    pid: registration.pid,
+                });
+            }
+            Ok(HandshakeProgress::PeerJoined { index, peer }) => {
+                match networking.peers.get_mut(index) {
+                    Some(joined) => *joined = peer,
+                    None => networking.peers.push(peer),
+                }
+            }
+            Ok(HandshakeProgress::WaitingForPeers { joined, expected }) => {
+                networking.peers_joined = joined;
+                networking.peers_expected = expected;
+                next_state.set(NorayState::WaitingForPeers);
+            }
+            Ok(HandshakeProgress::Connecting) => {
+                next_state.set(NorayState::Connecting);
+            }
+            Ok(HandshakeProgress::Connected(session)) => {
+                session
+                    .channels
+                    .host_authoritative
+                    .store(*sync_mode == SyncMode::HostAuthoritative, Ordering::Relaxed);
+                insert_session(&mut commands, *session);
+                networking.connected = true;
+                // Joiners wait for the host's start message.
+                let starts_now =
+                    role.as_deref().is_some_and(NorayRole::is_host) && manual_start.is_none();
+                next_state.set(if starts_now {
+                    NorayState::InGame
+                } else {
+                    NorayState::Ready
+                });
+                commands.remove_resource::<NorayHandshake>();
+                return;
+            }
+            Ok(HandshakeProgress::Failed(e)) => {
+                error!(error = %e, "noray handshake failed");
+                networking.error_message = e.to_string();
+                next_state.set(NorayState::Failed);
+                commands.remove_resource::<NorayHandshake>();
+                return;
+            }
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => {
+                networking.error_message = "Handshake cancelled".to_string();
+                next_state.set(NorayState::Failed);
+                commands.remove_resource::<NorayHandshake>();
+                return;
+            }
+        }
+    }
+}
+
+fn insert_session(commands: &mut Commands, session: NoraySession) {
+    commands.insert_resource(PlayerRegistrationInfo {
+        oid: session.registration.oid,
+        pid: session.registration.pid,
+    });
+    commands.insert_resource(RemoteUpdateReceiver {
+        receiver: Arc::new(session.channels.receiver),
+    });
+    commands.insert_resource(SyncChannel(session.channels.sync_tx));
+    commands.insert_resource(NetworkMessages {
+        tx: session.channels.message_tx,
+        rx: session.channels.message_rx,
+    });
+    commands.insert_resource(AuthorityChannels {
+        input_tx: session.channels.input_tx,
+        inputs: session.channels.inputs,
+        authoritative_tx: session.channels.authoritative_tx,
+        authoritative_rx: session.channels.authoritative_rx,
+    });
+    commands.insert_resource(RollbackChannels {
+        tx: session.channels.rollback_tx,
+        rx: session.channels.rollback_rx,
+    });
+    commands.insert_resource(SessionEvents(session.channels.events));
+    commands.insert_resource(SessionDisconnect(session.channels.disconnect));
+    commands.insert_resource(SessionStats(session.channels.stats));
+    commands.insert_resource(ClockChannels {
+        samples: session.channels.clock,
+        local_tick: session.channels.local_tick,
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn receive_channel_messages(
+    messages: Res<NetworkMessages>,
+    state: Res<State<NorayState>>,
+    mut next_state: ResMut<NextState<NorayState>>,
+    mut pending: ResMut<PendingReplication>,
+    network_events: Res<NetworkEventRegistry>,
+    mut pending_events: ResMut<PendingNetworkEvents>,
+    registration: Res<PlayerRegistrationInfo>,
+    role: Res<NorayRole>,
+    mut events: EventWriter<MessageReceived>,
+) {
+    pending_events.clear();
+
+    for message in messages.rx.try_iter() {
+        if message.channel == Channel::REPLICATION {
+            match bincode::deserialize::<ReplicationPacket>(&message.payload) {
+                Ok(packet) => pending.push(packet),
+                Err(e) => warn!(error = %e, "Dropping malformed replication message"),
+            }
+            continue;
+        }
+
+        if message.channel == Channel::CONTROL {
+            match bincode::deserialize::<ControlMessage>(&message.payload) {
+                Ok(ControlMessage::StartGame) => {
+                    if *state.get() == NorayState::Ready {
+                        next_state.set(NorayState::InGame);
+                    }
+                }
+                Err(e) => warn!(error = %e, "Dropping malformed control message"),
+            }
+            continue;
+        }
+
+        if network_events.is_event_channel(message.channel) {
+            match bincode::deserialize::<NetworkEventPacket>(&message.payload) {
+                Ok(packet) if is_targeted(&packet.target, &registration.oid, role.is_host()) => {
+                    trace!(from = %packet.from, event = packet.event, "Network event received");
+                    pending_events.push(packet);
+                }
+                Ok(_) => {}
+                Err(e) => warn!(error = %e, "Dropping malformed network event"),
+            }
+            continue;
+        }
+
+        events.send(MessageReceived(message));
+    }
+}
+
+fn receive_session_events(
+    session_events: Res<SessionEvents>,
+    mut networking: ResMut<NetworkingState>,
+    mut events: EventWriter<SessionEventReceived>,
+) {
+    for event in session_events.0.try_iter() {
+        match &event {
+            SessionEvent::ProtocolMismatch { peer, version } => {
+                networking.error_message = format!(
+                    "Peer {} uses protocol version {} (expected {})",
+                    peer, version, PROTOCOL_VERSION
+                );
+            }
+            SessionEvent::PeerLeft { .. } => {}
+        }
+
+        events.send(SessionEventReceived(event));
+    }
+}
+
+/// Lets the other peers despawn the local player right away instead of
+/// waiting for [`PeerTimeout`].
+fn send_disconnect_on_exit(
+    mut exit: EventReader<AppExit>,
+    disconnect: Option<Res<SessionDisconnect>>,
+    registration: Option<Res<PlayerRegistrationInfo>>,
+) {
+    if exit.read().next().is_none() {
+        return;
+    }
+
+    if let (Some(disconnect), Some(registration)) = (disconnect, registration)
+        && let Err(e) = disconnect.0.send(&registration.oid)
+    {
+        error!(error = %e, "Failed to announce disconnect");
+    }
+}
+
+pub fn sync_local_state(
+    query: Query<(&Transform, &Velocity, &IsJumping), With<LocalPlayerMarker>>,
+    tick: Res<SimulationTick>,
+    sync_tx: Res<SyncChannel>,
+    registration: Res<PlayerRegistrationInfo>,
+) {
+    for (transform, velocity, is_jumping) in query.iter() {
+        let state = GameState {
+            oid: registration.oid.clone(),
+            frame: tick.0,
+            x: transform.translation.x,
+            y: transform.translation.y,
+            vx: velocity.x,
+            vy: velocity.y,
+            is_jumping: is_jumping.0,
+        };
+
+        let _ = sync_tx.0.send(state);
+    }
+}
+
+fn advance_simulation_tick(mut tick: ResMut<SimulationTick>) {
+    tick.0 = tick.0.wrapping_add(1);
+}
+
+/// Resets a joiner's tick to the host's estimated tick when the two drift
+/// apart, e.g. right after joining or after a long stall.
+fn follow_host_tick(time: Res<NetworkTime>, mut tick: ResMut<SimulationTick>) {
+    let Some(host_tick) = time.host_tick() else {
+        return;
+    };
+
+    let host_tick = host_tick as u32;
+    if tick.0.abs_diff(host_tick) > MAX_TICK_DRIFT {
+        tick.0 = host_tick;
+    }
+}
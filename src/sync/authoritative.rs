@@ -0,0 +1,272 @@
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, Sender};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use super::RemotePlayerData;
+use super::receive::PeerConnected;
+use crate::game::local_input::LocalInputLatch;
+use crate::game::player::{IsJumping, Player, SPAWN_POSITION, Velocity, simulate, spawn_player};
+use crate::local_player_data::LocalPlayerMarker;
+use crate::network::{AuthoritativeState, GameState, InputCommand, PlayerInput};
+use crate::{PlayerRegistrationInfo, SimulationTick};
+
+/// Inputs sent with every command, so a lost datagram does not lose input.
+const INPUT_REDUNDANCY: usize = 3;
+/// Unacknowledged inputs kept for re-simulation.
+const MAX_PENDING_INPUTS: usize = 256;
+/// Received inputs a joiner may be ahead of the host by. Older ones are
+/// dropped, so a joiner ticking faster than the host cannot build up lag.
+const MAX_QUEUED_INPUTS: usize = 8;
+
+/// Session channels used in host-authoritative mode.
+#[derive(Resource)]
+pub struct AuthorityChannels {
+    pub input_tx: Sender<InputCommand>,
+    pub inputs: Receiver<InputCommand>,
+    pub authoritative_tx: Sender<AuthoritativeState>,
+    pub authoritative_rx: Receiver<AuthoritativeState>,
+}
+
+/// Local inputs the host has not acknowledged yet, replayed on top of every
+/// authoritative state.
+#[derive(Resource, Debug)]
+pub struct PendingInputs {
+    next_sequence: u32,
+    acknowledged: u32,
+    inputs: VecDeque<InputCommand>,
+}
+
+impl Default for PendingInputs {
+    fn default() -> Self {
+        // Sequence 0 stands for "nothing acknowledged yet".
+        Self {
+            next_sequence: 1,
+            acknowledged: 0,
+            inputs: VecDeque::new(),
+        }
+    }
+}
+
+/// A joiner's player simulated by the host from its inputs.
+///
+/// Joiners send one input per tick, so the host simulates at most one per
+/// tick too: sending more inputs does not make a player move faster.
+#[derive(Component, Debug, Default)]
+pub struct SimulatedPlayer {
+    pub last_input: u32,
+    /// Inputs newer than `last_input` waiting for their tick, by sequence.
+    queued: BTreeMap<u32, PlayerInput>,
+}
+
+impl SimulatedPlayer {
+    fn queue(&mut self, sequence: u32, input: PlayerInput) {
+        if sequence > self.last_input {
+            self.queued.insert(sequence, input);
+        }
+        while self.queued.len() > MAX_QUEUED_INPUTS {
+            self.queued.pop_first();
+        }
+    }
+
+    /// Takes the input to simulate this tick, if any arrived.
+    fn next_input(&mut self) -> Option<PlayerInput> {
+        let (sequence, input) = self.queued.pop_first()?;
+        self.last_input = sequence;
+        Some(input)
+    }
+}
+
+/// Sends this tick's local input to the host. The local player has already
+/// moved by it, so the joiner stays responsive while the host catches up.
+pub fn send_local_input(
+    latch: Res<LocalInputLatch>,
+    time: Res<Time>,
+    registration: Res<PlayerRegistrationInfo>,
+    channels: Res<AuthorityChannels>,
+    mut pending: ResMut<PendingInputs>,
+    local_players: Query<(), With<LocalPlayerMarker>>,
+) {
+    if local_players.is_empty() {
+        return;
+    }
+
+    let command = InputCommand {
+        oid: registration.oid.clone(),
+        sequence: pending.next_sequence,
+        input: latch.input(time.delta_seconds()),
+    };
+    pending.next_sequence += 1;
+    pending.inputs.push_back(command);
+
+    while pending.inputs.len() > MAX_PENDING_INPUTS {
+        pending.inputs.pop_front();
+    }
+
+    let resend = pending.inputs.len().saturating_sub(INPUT_REDUNDANCY);
+    for command in pending.inputs.range(resend..) {
+        let _ = channels.input_tx.send(command.clone());
+    }
+}
+
+/// Moves the local player to the newest state the host simulated for it and
+/// replays the inputs the host has not seen yet.
+pub fn reconcile_local_player(
+    registration: Res<PlayerRegistrationInfo>,
+    channels: Res<AuthorityChannels>,
+    mut pending: ResMut<PendingInputs>,
+    mut query: Query<(&mut Transform, &mut Velocity, &mut IsJumping), With<LocalPlayerMarker>>,
+) {
+    let newest = channels
+        .authoritative_rx
+        .try_iter()
+        .filter(|authoritative| authoritative.state.oid == registration.oid)
+        .max_by_key(|authoritative| authoritative.last_input);
+
+    let Some(AuthoritativeState { last_input, state }) = newest else {
+        return;
+    };
+
+    // Datagrams may arrive out of order.
+    if last_input < pending.acknowledged {
+        return;
+    }
+
+    pending.acknowledged = last_input;
+    pending
+        .inputs
+        .retain(|command| command.sequence > last_input);
+
+    for (mut transform, mut velocity, mut is_jumping) in query.iter_mut() {
+        transform.translation.x = state.x;
+        transform.translation.y = state.y;
+        velocity.x = state.vx;
+        velocity.y = state.vy;
+        is_jumping.0 = state.is_jumping;
+
+        for command in &pending.inputs {
+            simulate(
+                &mut transform,
+                &mut velocity,
+                &mut is_jumping,
+                &command.input,
+            );
+        }
+    }
+}
+
+/// Applies joiner inputs to their players on the host, one per player and
+/// tick, spawning a player the first time its input arrives.
+pub fn simulate_remote_inputs(
+    mut commands: Commands,
+    channels: Res<AuthorityChannels>,
+    mut remote_data: ResMut<RemotePlayerData>,
+    time: Res<Time>,
+    mut connected: EventWriter<PeerConnected>,
+    mut players: Query<(
+        &Player,
+        &mut SimulatedPlayer,
+        &mut Transform,
+        &mut Velocity,
+        &mut IsJumping,
+    )>,
+) {
+    let now = time.elapsed_seconds_f64();
+    // No input may cover more than a tick, or a joiner could speed itself up
+    // by claiming long frames.
+    let tick_length = time.delta_seconds();
+    let mut joined: HashMap<String, (SimulatedPlayer, Transform, Velocity, IsJumping)> =
+        HashMap::new();
+
+    for command in channels.inputs.try_iter() {
+        if remote_data.departed.contains(&command.oid) {
+            continue;
+        }
+
+        if remote_data
+            .last_seen
+            .insert(command.oid.clone(), now)
+            .is_none()
+        {
+            connected.send(PeerConnected {
+                oid: command.oid.clone(),
+            });
+        }
+
+        let input = PlayerInput {
+            direction: command.input.direction.signum(),
+            jump: command.input.jump,
+            dt: command.input.dt.clamp(0.0, tick_length),
+        };
+
+        match players
+            .iter_mut()
+            .find(|(player, ..)| player.oid == command.oid)
+        {
+            Some((_, mut simulated, ..)) => simulated.queue(command.sequence, input),
+            None => joined
+                .entry(command.oid.clone())
+                .or_insert_with(|| {
+                    (
+                        SimulatedPlayer::default(),
+                        Transform::from_translation(SPAWN_POSITION),
+                        Velocity::default(),
+                        IsJumping::default(),
+                    )
+                })
+                .0
+                .queue(command.sequence, input),
+        }
+    }
+
+    for (_, mut simulated, mut transform, mut velocity, mut is_jumping) in players.iter_mut() {
+        // Checked first so idle players are not marked as changed.
+        if simulated.queued.is_empty() {
+            continue;
+        }
+        if let Some(input) = simulated.next_input() {
+            simulate(&mut transform, &mut velocity, &mut is_jumping, &input);
+        }
+    }
+
+    for (oid, (mut simulated, mut transform, mut velocity, mut is_jumping)) in joined {
+        if let Some(input) = simulated.next_input() {
+            simulate(&mut transform, &mut velocity, &mut is_jumping, &input);
+        }
+
+        let entity = spawn_player(
+            &mut commands,
+            oid,
+            false,
+            transform.translation,
+            Color::srgb(1.0, 0.0, 0.0),
+        );
+        commands
+            .entity(entity)
+            .insert((simulated, velocity, is_jumping));
+    }
+}
+
+/// Sends the state of every joiner player that moved to all peers.
+pub fn send_authoritative_states(
+    channels: Res<AuthorityChannels>,
+    tick: Res<SimulationTick>,
+    players: Query<
+        (&Player, &SimulatedPlayer, &Transform, &Velocity, &IsJumping),
+        Changed<SimulatedPlayer>,
+    >,
+) {
+    for (player, simulated, transform, velocity, is_jumping) in players.iter() {
+        let _ = channels.authoritative_tx.send(AuthoritativeState {
+            last_input: simulated.last_input,
+            state: GameState {
+                oid: player.oid.clone(),
+                frame: tick.0,
+                x: transform.translation.x,
+                y: transform.translation.y,
+                vx: velocity.x,
+                vy: velocity.y,
+                is_jumping: is_jumping.0,
+            },
+        });
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+
+use super::network_entity::{NetworkEntityMap, NetworkId};
+use super::replication::{OutgoingReplication, ReplicationMessage};
+use crate::PlayerRegistrationInfo;
+
+/// The peer allowed to change a networked entity: only its replicated
+/// updates, despawn and authority grants are applied by the other peers.
+///
+/// Starts with the peer that spawned the entity, or the player itself for a
+/// `Player`, and moves with [`RequestAuthority`].
+#[derive(Component, Debug, Clone, PartialEq, Eq)]
+pub struct Authority {
+    pub owner: String,
+}
+
+impl Authority {
+    pub fn new(owner: impl Into<String>) -> Self {
+        Self {
+            owner: owner.into(),
+        }
+    }
+
+    pub fn is_owned_by(&self, oid: &str) -> bool {
+        self.owner == oid
+    }
+}
+
+/// Asks the current owner of a networked entity to hand its [`Authority`]
+/// to this peer, e.g. when picking up a shared ball. The owner grants
+/// requests in the order they arrive; players are never handed over.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct RequestAuthority {
+    pub entity: Entity,
+}
+
+/// The [`Authority`] over `entity` moved to `owner`, on this peer's copy.
+#[derive(Event, Debug, Clone)]
+pub struct AuthorityChanged {
+    pub entity: Entity,
+    pub id: NetworkId,
+    pub previous: String,
+    pub owner: String,
+}
+
+/// Sends [`RequestAuthority`] to the entities' current owners.
+pub fn send_authority_requests(
+    mut requests: EventReader<RequestAuthority>,
+    entities: Query<(&NetworkId, &Authority)>,
+    registration: Res<PlayerRegistrationInfo>,
+    mut outgoing: ResMut<OutgoingReplication>,
+) {
+    for request in requests.read() {
+        let Ok((id, authority)) = entities.get(request.entity) else {
+            warn!(entity = ?request.entity, "Requested authority over a non-networked entity");
+            continue;
+        };
+
+        if id.is_player() || authority.is_owned_by(&registration.oid) {
+            continue;
+        }
+
+        debug!(id = %id, owner = %authority.owner, "Requesting authority");
+        outgoing.push(ReplicationMessage::RequestAuthority {
+            id: id.clone(),
+            owner: authority.owner.clone(),
+        });
+    }
+}
+
+/// Handles a [`ReplicationMessage::RequestAuthority`] from `from`, granting
+/// it if this peer still owns the entity.
+pub(crate) fn grant_authority(
+    world: &mut World,
+    map: &NetworkEntityMap,
+    from: &str,
+    id: &NetworkId,
+    owner: &str,
+) {
+    let own_oid = world.resource::<PlayerRegistrationInfo>().oid.clone();
+    // Requests are relayed to everyone but only the addressed owner answers,
+    // so two peers never grant the same entity.
+    if owner != own_oid || id.is_player() {
+        return;
+    }
+
+    if transfer(world, map, &own_oid, id, from) {
+        world
+            .resource_mut::<OutgoingReplication>()
+            .push(ReplicationMessage::GrantAuthority {
+                id: id.clone(),
+                owner: from.to_string(),
+            });
+    }
+}
+
+/// Moves the authority over `id` from `from` to `owner`, if `from` holds it.
+pub(crate) fn transfer(
+    world: &mut World,
+    map: &NetworkEntityMap,
+    from: &str,
+    id: &NetworkId,
+    owner: &str,
+) -> bool {
+    let Some(entity) = map.entity(id) else {
+        return false;
+    };
+    let Some(mut authority) = world.get_mut::<Authority>(entity) else {
+        return false;
+    };
+    if !authority.is_owned_by(from) {
+        debug!(id = %id, from, owner = %authority.owner, "Ignoring grant from a non-owner");
+        return false;
+    }
+
+    let previous = std::mem::replace(&mut authority.owner, owner.to_string());
+    info!(id = %id, previous = %previous, owner, "Authority transferred");
+    world.send_event(AuthorityChanged {
+        entity,
+        id: id.clone(),
+        previous,
+        owner: owner.to_string(),
+    });
+    true
+}
+
+/// Hands the entities of a departed peer back to the peers that spawned
+/// them.
+pub fn return_departed_authority(
+    mut disconnected: EventReader<super::PeerDisconnected>,
+    mut entities: Query<(Entity, &NetworkId, &mut Authority)>,
+    mut changed: EventWriter<AuthorityChanged>,
+) {
+    for event in disconnected.read() {
+        for (entity, id, mut authority) in entities.iter_mut() {
+            if authority.is_owned_by(&event.oid) && id.owner != event.oid {
+                let previous = std::mem::replace(&mut authority.owner, id.owner.clone());
+                changed.send(AuthorityChanged {
+                    entity,
+                    id: id.clone(),
+                    previous,
+                    owner: id.owner.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// Entities this peer has authority over, so their despawn can be sent
+/// after the component is gone.
+#[derive(Default)]
+pub struct OwnedEntities(HashSet<Entity>);
+
+impl OwnedEntities {
+    pub fn update(&mut self, entity: Entity, authority: &Authority, own_oid: &str) {
+        if authority.is_owned_by(own_oid) {
+            self.0.insert(entity);
+        } else {
+            self.0.remove(&entity);
+        }
+    }
+
+    pub fn remove(&mut self, entity: Entity) -> bool {
+        self.0.remove(&entity)
+    }
+}
@@ -0,0 +1,197 @@
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::network::GameState;
+
+/// How remote players are rendered from their received snapshots.
+#[derive(Resource, Debug, Clone)]
+pub struct InterpolationConfig {
+    /// How far behind the newest snapshot remote players are rendered. Larger
+    /// values hide more jitter and loss at the cost of latency.
+    pub delay: Duration,
+    /// Longest a player is moved along its last velocity once snapshots run
+    /// out, before it is held in place.
+    pub max_extrapolation: Duration,
+    /// Snapshots kept per remote player.
+    pub buffer_size: usize,
+}
+
+impl Default for InterpolationConfig {
+    fn default() -> Self {
+        Self {
+            delay: Duration::from_millis(100),
+            max_extrapolation: Duration::from_millis(250),
+            buffer_size: 32,
+        }
+    }
+}
+
+/// A `GameState` stamped with the local time it arrived at.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub received_at: f64,
+    pub state: GameState,
+}
+
+/// Recent snapshots of one remote player, oldest first.
+#[derive(Component, Debug, Default)]
+pub struct SnapshotBuffer {
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl SnapshotBuffer {
+    /// Adds a snapshot, dropping it if it is older than the newest one (UDP
+    /// does not preserve order) and evicting the oldest beyond `capacity`.
+    pub fn push(&mut self, received_at: f64, state: GameState, capacity: usize) {
+        if let Some(newest) = self.snapshots.back()
+            && state.frame <= newest.state.frame
+        {
+            return;
+        }
+
+        self.snapshots.push_back(Snapshot { received_at, state });
+
+        while self.snapshots.len() > capacity.max(2) {
+            self.snapshots.pop_front();
+        }
+    }
+
+    pub fn newest(&self) -> Option<&Snapshot> {
+        self.snapshots.back()
+    }
+
+    /// Position at `render_time`, interpolated between the two snapshots
+    /// around it, or extrapolated from the newest one if none is that recent.
+    pub fn sample(&self, render_time: f64, max_extrapolation: Duration) -> Option<Vec2> {
+        let oldest = self.snapshots.front()?;
+        if render_time <= oldest.received_at {
+            return Some(Vec2::new(oldest.state.x, oldest.state.y));
+        }
+
+        for (from, to) in self.snapshots.iter().zip(self.snapshots.iter().skip(1)) {
+            if render_time <= to.received_at {
+                let span = to.received_at - from.received_at;
+                let t = if span > 0.0 {
+                    ((render_time - from.received_at) / span) as f32
+                } else {
+                    1.0
+                };
+
+                return Some(
+                    Vec2::new(from.state.x, from.state.y)
+                        .lerp(Vec2::new(to.state.x, to.state.y), t),
+                );
+            }
+        }
+
+        let newest = self.snapshots.back()?;
+        let late = (render_time - newest.received_at).min(max_extrapolation.as_secs_f64()) as f32;
+
+        Some(
+            Vec2::new(newest.state.x, newest.state.y)
+                + Vec2::new(newest.state.vx, newest.state.vy) * late,
+        )
+    }
+
+    /// Forgets snapshots that can no longer be sampled, keeping the last one
+    /// before `render_time` to interpolate from.
+    pub fn discard_before(&mut self, render_time: f64) {
+        while self.snapshots.len() > 2 && self.snapshots[1].received_at <= render_time {
+            self.snapshots.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_EXTRAPOLATION: Duration = Duration::from_millis(250);
+
+    fn state(frame: u32, x: f32, vx: f32) -> GameState {
+        GameState {
+            oid: "player".to_string(),
+            frame,
+            x,
+            y: 0.0,
+            vx,
+            vy: 0.0,
+            is_jumping: false,
+        }
+    }
+
+    fn buffer(frames: &[(f64, u32, f32)]) -> SnapshotBuffer {
+        let mut buffer = SnapshotBuffer::default();
+        for &(received_at, frame, x) in frames {
+            buffer.push(received_at, state(frame, x, 100.0), 32);
+        }
+        buffer
+    }
+
+    fn x_at(buffer: &SnapshotBuffer, render_time: f64) -> f32 {
+        buffer.sample(render_time, MAX_EXTRAPOLATION).unwrap().x
+    }
+
+    #[test]
+    fn positions_between_snapshots_are_interpolated() {
+        let buffer = buffer(&[(1.0, 1, 0.0), (2.0, 2, 10.0)]);
+
+        assert_eq!(x_at(&buffer, 0.5), 0.0);
+        assert_eq!(x_at(&buffer, 1.25), 2.5);
+        assert_eq!(x_at(&buffer, 2.0), 10.0);
+        assert!(
+            SnapshotBuffer::default()
+                .sample(1.0, MAX_EXTRAPOLATION)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn extrapolation_past_the_newest_snapshot_is_clamped() {
+        let buffer = buffer(&[(1.0, 1, 0.0), (2.0, 2, 10.0)]);
+
+        assert_eq!(x_at(&buffer, 2.1), 20.0);
+        assert_eq!(x_at(&buffer, 2.25), 35.0);
+        assert_eq!(x_at(&buffer, 10.0), 35.0);
+    }
+
+    #[test]
+    fn snapshots_not_newer_than_the_newest_are_dropped() {
+        let mut buffer = buffer(&[(1.0, 5, 0.0)]);
+        buffer.push(2.0, state(5, 10.0, 0.0), 32);
+        buffer.push(3.0, state(4, 20.0, 0.0), 32);
+
+        let newest = buffer.newest().unwrap();
+        assert_eq!((newest.state.frame, newest.received_at), (5, 1.0));
+    }
+
+    #[test]
+    fn the_oldest_snapshots_are_evicted_beyond_capacity() {
+        let mut buffer = SnapshotBuffer::default();
+        for frame in 1..=4 {
+            buffer.push(frame as f64, state(frame, frame as f32, 0.0), 2);
+        }
+
+        assert_eq!(x_at(&buffer, 0.0), 3.0);
+    }
+
+    #[test]
+    fn discarding_keeps_the_snapshot_to_interpolate_from() {
+        let mut buffer = buffer(&[
+            (1.0, 1, 0.0),
+            (2.0, 2, 10.0),
+            (3.0, 3, 20.0),
+            (4.0, 4, 30.0),
+        ]);
+
+        buffer.discard_before(2.5);
+        assert_eq!(x_at(&buffer, 0.0), 10.0);
+        assert_eq!(x_at(&buffer, 2.5), 15.0);
+
+        // The last two are always kept.
+        buffer.discard_before(100.0);
+        assert_eq!(x_at(&buffer, 0.0), 20.0);
+        assert_eq!(x_at(&buffer, 3.5), 25.0);
+    }
+}
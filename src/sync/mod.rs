@@ -1,5 +1,29 @@
+pub mod authoritative;
+pub mod authority;
+pub mod interpolation;
+pub mod network_entity;
+pub mod network_event;
+pub mod network_time;
 pub mod receive;
 pub mod remote_player;
+pub mod replication;
+pub mod rollback;
+pub mod stats;
 
-pub use receive::{RemoteUpdateReceiver, receive_remote_updates};
+pub use authoritative::{AuthorityChannels, PendingInputs, SimulatedPlayer};
+pub use authority::{Authority, AuthorityChanged, RequestAuthority};
+pub use interpolation::{InterpolationConfig, Snapshot, SnapshotBuffer};
+pub use network_entity::{NetworkEntityMap, NetworkId};
+pub use network_event::{AppNetworkEventExt, NetworkEvent, NetworkEventRegistry, NetworkTarget};
+pub use network_time::{ClockChannels, NetworkTime};
+pub use receive::{
+    DisconnectReason, PeerConnected, PeerDisconnected, PeerTimeout, RemoteUpdateReceiver,
+    despawn_disconnected_players, detect_disconnected_peers, receive_remote_updates,
+};
 pub use remote_player::{RemotePlayerData, update_remote_player_transforms};
+pub use replication::{
+    AppReplicateExt, PendingReplication, Replicated, ReplicationMessage, ReplicationPacket,
+    ReplicationRegistry, ReplicationUpdate,
+};
+pub use rollback::{RollbackChannels, RollbackConfig, RollbackSession};
+pub use stats::{NetworkStats, PeerNetworkStats, SessionStats};
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Names an entity the same way on every peer: the OID of the peer that
+/// spawned it and a serial number unique to that peer. Serial 0 is reserved
+/// for the owner's `Player`.
+#[derive(Component, Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NetworkId {
+    pub owner: String,
+    pub serial: u32,
+}
+
+impl NetworkId {
+    pub fn player(oid: impl Into<String>) -> Self {
+        Self {
+            owner: oid.into(),
+            serial: 0,
+        }
+    }
+
+    pub fn is_player(&self) -> bool {
+        self.serial == 0
+    }
+}
+
+impl fmt::Display for NetworkId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.owner, self.serial)
+    }
+}
+
+/// Maps between local entities and their [`NetworkId`]s, for both the
+/// entities this peer spawned and the copies of other peers' entities.
+#[derive(Resource, Debug)]
+pub struct NetworkEntityMap {
+    entities: HashMap<NetworkId, Entity>,
+    ids: HashMap<Entity, NetworkId>,
+    next_serial: u32,
+}
+
+impl Default for NetworkEntityMap {
+    fn default() -> Self {
+        Self {
+            entities: HashMap::new(),
+            ids: HashMap::new(),
+            next_serial: 1,
+        }
+    }
+}
+
+impl NetworkEntityMap {
+    /// A fresh id for an entity spawned by `owner`, i.e. this peer.
+    pub fn allocate(&mut self, owner: &str) -> NetworkId {
+        let id = NetworkId {
+            owner: owner.to_string(),
+            serial: self.next_serial,
+        };
+        self.next_serial += 1;
+        id
+    }
+
+    pub fn entity(&self, id: &NetworkId) -> Option<Entity> {
+        self.entities.get(id).copied()
+    }
+
+    pub fn network_id(&self, entity: Entity) -> Option<&NetworkId> {
+        self.ids.get(&entity)
+    }
+
+    pub fn insert(&mut self, id: NetworkId, entity: Entity) {
+        self.ids.insert(entity, id.clone());
+        self.entities.insert(id, entity);
+    }
+
+    pub fn remove_entity(&mut self, entity: Entity) -> Option<NetworkId> {
+        let id = self.ids.remove(&entity)?;
+        self.entities.remove(&id);
+        Some(id)
+    }
+
+    /// Entities spawned by `owner`.
+    pub fn owned_by<'a>(&'a self, owner: &'a str) -> impl Iterator<Item = Entity> + 'a {
+        self.entities
+            .iter()
+            .filter(move |(id, _)| id.owner == owner)
+            .map(|(_, entity)| *entity)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
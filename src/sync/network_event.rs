@@ -0,0 +1,211 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::network::Channel;
+use crate::{NetworkMessages, NorayRole, NoraySet, PlayerRegistrationInfo};
+
+/// Which peers a [`NetworkEvent`] is emitted on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NetworkTarget {
+    /// Every peer, the sender included.
+    All,
+    Host,
+    /// The peer registered under this OID.
+    Peer(String),
+}
+
+/// Sends `event` to the [`NetworkTarget`] peers, where it is emitted as a
+/// plain `T` event. Register `T` with [`AppNetworkEventExt::add_network_event`]
+/// first.
+#[derive(Event, Debug, Clone)]
+pub struct NetworkEvent<T> {
+    pub target: NetworkTarget,
+    pub event: T,
+}
+
+impl<T> NetworkEvent<T> {
+    pub fn new(target: NetworkTarget, event: T) -> Self {
+        Self { target, event }
+    }
+}
+
+/// Registers typed network events; see [`AppNetworkEventExt::add_network_event`].
+pub trait AppNetworkEventExt {
+    /// Sends every [`NetworkEvent<T>`] written on this peer over `channel`
+    /// and emits `T` on the targeted peers.
+    ///
+    /// `channel` is reserved for network events from then on: its messages
+    /// no longer show up as `MessageReceived`. Several event types may share
+    /// it. Every peer must register the same events in the same order.
+    fn add_network_event<T>(&mut self, channel: Channel) -> &mut Self
+    where
+        T: Event + Clone + Serialize + DeserializeOwned;
+}
+
+impl AppNetworkEventExt for App {
+    fn add_network_event<T>(&mut self, channel: Channel) -> &mut Self
+    where
+        T: Event + Clone + Serialize + DeserializeOwned,
+    {
+        assert!(
+            channel != Channel::REPLICATION && channel != Channel::CONTROL,
+            "Channel::REPLICATION and Channel::CONTROL are reserved"
+        );
+
+        self.add_event::<T>()
+            .add_event::<NetworkEvent<T>>()
+            .init_resource::<NetworkEventRegistry>()
+            .init_resource::<PendingNetworkEvents>();
+
+        let mut registry = self.world_mut().resource_mut::<NetworkEventRegistry>();
+        if registry.index_of(TypeId::of::<T>()).is_none() {
+            registry.events.push((TypeId::of::<T>(), channel));
+            self.add_systems(
+                Update,
+                (
+                    send_network_events::<T>.in_set(NoraySet::Send),
+                    receive_network_events::<T>
+                        .after(crate::plugin::receive_channel_messages)
+                        .in_set(NoraySet::Receive),
+                ),
+            );
+        }
+        self
+    }
+}
+
+/// Registered network event types, indexed by registration order on the
+/// wire, and the channel each is sent on.
+#[derive(Resource, Debug, Default)]
+pub struct NetworkEventRegistry {
+    events: Vec<(TypeId, Channel)>,
+}
+
+impl NetworkEventRegistry {
+    fn index_of(&self, type_id: TypeId) -> Option<u16> {
+        self.events
+            .iter()
+            .position(|(id, _)| *id == type_id)
+            .map(|index| index as u16)
+    }
+
+    /// Whether messages on `channel` carry network events.
+    pub fn is_event_channel(&self, channel: Channel) -> bool {
+        self.events.iter().any(|(_, other)| *other == channel)
+    }
+}
+
+/// A network event on the wire.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkEventPacket {
+    /// Registry index of the event type.
+    pub event: u16,
+    pub from: String,
+    pub target: NetworkTarget,
+    pub payload: Vec<u8>,
+}
+
+/// Received payloads addressed to this peer, by event type, until the
+/// type's receive system emits them.
+#[derive(Resource, Debug, Default)]
+pub struct PendingNetworkEvents {
+    events: HashMap<u16, Vec<Vec<u8>>>,
+}
+
+impl PendingNetworkEvents {
+    pub fn push(&mut self, packet: NetworkEventPacket) {
+        self.events
+            .entry(packet.event)
+            .or_default()
+            .push(packet.payload);
+    }
+
+    /// Drops payloads of event types no system took.
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+}
+
+/// Whether `target` includes the peer `oid`, which is the host if `is_host`.
+pub fn is_targeted(target: &NetworkTarget, oid: &str, is_host: bool) -> bool {
+    match target {
+        NetworkTarget::All => true,
+        NetworkTarget::Host => is_host,
+        NetworkTarget::Peer(peer) => peer == oid,
+    }
+}
+
+fn send_network_events<T: Event + Clone + Serialize>(
+    mut outgoing: EventReader<NetworkEvent<T>>,
+    mut local: EventWriter<T>,
+    registry: Res<NetworkEventRegistry>,
+    registration: Res<PlayerRegistrationInfo>,
+    role: Res<NorayRole>,
+    messages: Option<Res<NetworkMessages>>,
+) {
+    let Some(index) = registry.index_of(TypeId::of::<T>()) else {
+        return;
+    };
+    let channel = registry.events[index as usize].1;
+
+    for NetworkEvent { target, event } in outgoing.read() {
+        if is_targeted(target, &registration.oid, role.is_host()) {
+            local.send(event.clone());
+        }
+
+        // Sent to ourselves only; nobody else needs it.
+        if *target == NetworkTarget::Peer(registration.oid.clone())
+            || (*target == NetworkTarget::Host && role.is_host())
+        {
+            continue;
+        }
+
+        let Some(messages) = &messages else {
+            continue;
+        };
+
+        let packet = bincode::serialize(event).and_then(|payload| {
+            bincode::serialize(&NetworkEventPacket {
+                event: index,
+                from: registration.oid.clone(),
+                target: target.clone(),
+                payload,
+            })
+        });
+        match packet {
+            Ok(packet) => messages.send(channel, packet),
+            Err(e) => warn!(
+                event = std::any::type_name::<T>(),
+                error = %e,
+                "Failed to encode network event"
+            ),
+        }
+    }
+}
+
+fn receive_network_events<T: Event + DeserializeOwned>(
+    mut pending: ResMut<PendingNetworkEvents>,
+    registry: Res<NetworkEventRegistry>,
+    mut events: EventWriter<T>,
+) {
+    let Some(index) = registry.index_of(TypeId::of::<T>()) else {
+        return;
+    };
+
+    for payload in pending.events.remove(&index).unwrap_or_default() {
+        match bincode::deserialize::<T>(&payload) {
+            Ok(event) => {
+                events.send(event);
+            }
+            Err(e) => warn!(
+                event = std::any::type_name::<T>(),
+                error = %e,
+                "Dropping malformed network event"
+            ),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use bevy::prelude::*;
+use crossbeam_channel::Receiver;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::SimulationTick;
+use crate::network::ClockSample;
+
+/// Session channels used for clock synchronization.
+#[derive(Resource)]
+pub struct ClockChannels {
+    pub samples: Receiver<ClockSample>,
+    pub local_tick: Arc<AtomicU32>,
+}
+
+/// Round-trip time and clock offset of every peer, and the session's shared
+/// notion of "now" as the host's simulation tick.
+#[derive(Resource, Debug)]
+pub struct NetworkTime {
+    tick_rate: f64,
+    is_host: bool,
+    local_tick: u32,
+    peers: HashMap<SocketAddr, ClockSample>,
+}
+
+impl NetworkTime {
+    pub fn new(tick_rate: f64, is_host: bool) -> Self {
+        Self {
+            tick_rate,
+            is_host,
+            local_tick: 0,
+            peers: HashMap::new(),
+        }
+    }
+
+    pub fn rtt(&self, peer: SocketAddr) -> Option<Duration> {
+        self.peers.get(&peer).map(|sample| sample.rtt)
+    }
+
+    /// Peer clock minus local clock, in seconds.
+    pub fn offset(&self, peer: SocketAddr) -> Option<f64> {
+        self.peers.get(&peer).map(|sample| sample.offset)
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &ClockSample> {
+        self.peers.values()
+    }
+
+    /// The tick the host is simulating right now, with fractions of a tick.
+    ///
+    /// On the host this is its own tick. Joiners only talk to the host, so
+    /// their single peer is the host; `None` until its first pong arrives.
+    pub fn host_tick(&self) -> Option<f64> {
+        self.host_tick_at(Instant::now())
+    }
+
+    pub fn host_tick_at(&self, now: Instant) -> Option<f64> {
+        if self.is_host {
+            return Some(self.local_tick as f64);
+        }
+
+        self.peers.values().next().map(|sample| {
+            let elapsed = now.saturating_duration_since(sample.remote_tick_at);
+            sample.remote_tick as f64 + elapsed.as_secs_f64() * self.tick_rate
+        })
+    }
+
+    pub fn record(&mut self, sample: ClockSample) {
+        self.peers.insert(sample.peer, sample);
+    }
+}
+
+pub fn update_network_time(
+    channels: Res<ClockChannels>,
+    tick: Res<SimulationTick>,
+    mut time: ResMut<NetworkTime>,
+) {
+    time.local_tick = tick.0;
+
+    for sample in channels.samples.try_iter() {
+        time.record(sample);
+    }
+}
+
+/// Makes the local tick available to the session thread for its pongs.
+pub fn publish_local_tick(channels: Res<ClockChannels>, tick: Res<SimulationTick>) {
+    channels.local_tick.store(tick.0, Ordering::Relaxed);
+}
@@ -1,30 +1,148 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use bevy::prelude::*;
 use crossbeam_channel::Receiver;
 
 use super::RemotePlayerData;
-use crate::PlayerRegistrationInfo;
-use crate::network::GameState;
+use super::interpolation::{InterpolationConfig, SnapshotBuffer};
+use crate::game::player::Player;
+use crate::local_player_data::LocalPlayerMarker;
+use crate::network::{GameState, SessionEvent};
+use crate::{PlayerRegistrationInfo, SessionEventReceived};
 
 #[derive(Resource)]
 pub struct RemoteUpdateReceiver {
     pub receiver: Arc<Receiver<GameState>>,
 }
 
+/// How long a remote player may go without sending state before it is
+/// considered gone.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct PeerTimeout(pub Duration);
+
+impl Default for PeerTimeout {
+    fn default() -> Self {
+        Self(Duration::from_secs(5))
+    }
+}
+
+/// The first state from a remote player arrived.
+#[derive(Event, Debug, Clone)]
+pub struct PeerConnected {
+    pub oid: String,
+}
+
+/// A remote player left or stopped sending state; its `Player` entity is
+/// despawned.
+#[derive(Event, Debug, Clone)]
+pub struct PeerDisconnected {
+    pub oid: String,
+    pub reason: DisconnectReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The peer sent an explicit disconnect message.
+    Left,
+    /// Nothing was heard from the peer for [`PeerTimeout`].
+    TimedOut,
+}
+
 pub fn receive_remote_updates(
     mut remote_data: ResMut<RemotePlayerData>,
     receiver: Option<Res<RemoteUpdateReceiver>>,
     registration: Res<PlayerRegistrationInfo>,
+    config: Res<InterpolationConfig>,
+    time: Res<Time>,
+    mut buffers: Query<(&Player, &mut SnapshotBuffer)>,
+    mut connected: EventWriter<PeerConnected>,
 ) {
     if let Some(rx) = receiver {
+        let now = time.elapsed_seconds_f64();
+
         while let Ok(state) = rx.receiver.try_recv() {
-            if state.oid != registration.oid {
-                remote_data.players.insert(
-                    state.oid.clone(),
-                    (state.x, state.y, state.vx, state.vy, state.is_jumping),
-                );
-                remote_data.initialized = true;
+            // Late packets must not bring back a player that already left.
+            if state.oid == registration.oid || remote_data.departed.contains(&state.oid) {
+                continue;
+            }
+
+            if remote_data
+                .last_seen
+                .insert(state.oid.clone(), now)
+                .is_none()
+            {
+                connected.send(PeerConnected {
+                    oid: state.oid.clone(),
+                });
+            }
+
+            remote_data.players.insert(
+                state.oid.clone(),
+                (state.x, state.y, state.vx, state.vy, state.is_jumping),
+            );
+            remote_data.initialized = true;
+
+            // Players spawned this frame get seeded from `players` instead.
+            if let Some((_, mut buffer)) = buffers
+                .iter_mut()
+                .find(|(player, _)| player.oid == state.oid)
+            {
+                buffer.push(now, state, config.buffer_size);
+            }
+        }
+    }
+}
+
+/// Drops remote players that announced they are leaving or timed out.
+pub fn detect_disconnected_peers(
+    mut remote_data: ResMut<RemotePlayerData>,
+    mut session_events: EventReader<SessionEventReceived>,
+    timeout: Res<PeerTimeout>,
+    time: Res<Time>,
+    mut disconnected: EventWriter<PeerDisconnected>,
+) {
+    let mut gone = Vec::new();
+
+    for SessionEventReceived(event) in session_events.read() {
+        if let SessionEvent::PeerLeft { oid, .. } = event {
+            gone.push((oid.clone(), DisconnectReason::Left));
+        }
+    }
+
+    let deadline = time.elapsed_seconds_f64() - timeout.0.as_secs_f64();
+    gone.extend(
+        remote_data
+            .last_seen
+            .iter()
+            .filter(|(_, last_seen)| **last_seen < deadline)
+            .map(|(oid, _)| (oid.clone(), DisconnectReason::TimedOut)),
+    );
+
+    for (oid, reason) in gone {
+        let was_known = remote_data.last_seen.remove(&oid).is_some();
+        remote_data.players.remove(&oid);
+
+        if reason == DisconnectReason::Left {
+            remote_data.departed.insert(oid.clone());
+        }
+
+        if was_known {
+            info!(oid = %oid, reason = ?reason, "Peer disconnected");
+            disconnected.send(PeerDisconnected { oid, reason });
+        }
+    }
+}
+
+pub fn despawn_disconnected_players(
+    mut commands: Commands,
+    mut disconnected: EventReader<PeerDisconnected>,
+    players: Query<(Entity, &Player), Without<LocalPlayerMarker>>,
+) {
+    for event in disconnected.read() {
+        for (entity, player) in players.iter() {
+            if player.oid == event.oid {
+                commands.entity(entity).despawn_recursive();
             }
         }
     }
@@ -1,45 +1,85 @@
 use bevy::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+use super::interpolation::{InterpolationConfig, SnapshotBuffer};
 pub use crate::game::player::{Player, spawn_player};
+use crate::network::GameState;
+
+type RemotePlayerQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static Player,
+        &'static mut Transform,
+        &'static mut SnapshotBuffer,
+    ),
+    (
+        With<Player>,
+        Without<crate::local_player_data::LocalPlayerMarker>,
+    ),
+>;
 
 #[derive(Resource, Default)]
 pub struct RemotePlayerData {
     pub players: HashMap<String, (f32, f32, f32, f32, bool)>,
     pub initialized: bool,
+    /// When state from each remote player last arrived, in seconds since
+    /// startup.
+    pub last_seen: HashMap<String, f64>,
+    /// Players that announced they left; their late packets are ignored.
+    pub departed: HashSet<String>,
 }
 
+/// Renders every remote player `InterpolationConfig::delay` behind the
+/// newest snapshot received from it, spawning players seen for the first time.
 pub fn update_remote_player_transforms(
     mut commands: Commands,
     remote_data: Res<RemotePlayerData>,
-    mut remote_query: Query<
-        (Entity, &Player, &mut Transform),
-        (
-            With<Player>,
-            Without<crate::local_player_data::LocalPlayerMarker>,
-        ),
-    >,
+    config: Res<InterpolationConfig>,
+    time: Res<Time>,
+    mut remote_query: RemotePlayerQuery,
 ) {
-    let mut oid_to_entity = HashMap::new();
+    let now = time.elapsed_seconds_f64();
+    let render_time = now - config.delay.as_secs_f64();
+    let mut spawned = HashSet::new();
+
+    for (player, mut transform, mut buffer) in remote_query.iter_mut() {
+        spawned.insert(player.oid.clone());
 
-    for (entity, player, _transform) in remote_query.iter() {
-        oid_to_entity.insert(player.oid.clone(), entity);
+        if let Some(position) = buffer.sample(render_time, config.max_extrapolation) {
+            transform.translation.x = position.x;
+            transform.translation.y = position.y;
+        }
+        buffer.discard_before(render_time);
     }
 
-    for (oid, (x, y, _vx, _vy, _is_jumping)) in remote_data.players.iter() {
-        if let Some(entity) = oid_to_entity.get(oid) {
-            if let Ok((_, _, mut transform)) = remote_query.get_mut(*entity) {
-                transform.translation.x = *x;
-                transform.translation.y = *y;
-            }
-        } else {
-            spawn_player(
-                &mut commands,
-                oid.clone(),
-                false,
-                Vec3::new(*x, *y, 0.0),
-                Color::srgb(1.0, 0.0, 0.0),
-            );
+    for (oid, (x, y, vx, vy, is_jumping)) in remote_data.players.iter() {
+        if spawned.contains(oid) {
+            continue;
         }
+
+        let mut buffer = SnapshotBuffer::default();
+        buffer.push(
+            now,
+            GameState {
+                oid: oid.clone(),
+                frame: 0,
+                x: *x,
+                y: *y,
+                vx: *vx,
+                vy: *vy,
+                is_jumping: *is_jumping,
+            },
+            config.buffer_size,
+        );
+
+        let entity = spawn_player(
+            &mut commands,
+            oid.clone(),
+            false,
+            Vec3::new(*x, *y, 0.0),
+            Color::srgb(1.0, 0.0, 0.0),
+        );
+        commands.entity(entity).insert(buffer);
     }
 }
@@ -0,0 +1,474 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+
+use bevy::ecs::reflect::ReflectComponent;
+use bevy::hierarchy::despawn_with_children_recursive;
+use bevy::prelude::*;
+use bevy::reflect::serde::{TypedReflectDeserializer, TypedReflectSerializer};
+use bevy::reflect::{GetTypeRegistration, TypeRegistry};
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+use super::authority::{self, Authority, OwnedEntities};
+use super::network_entity::{NetworkEntityMap, NetworkId};
+use super::receive::PeerDisconnected;
+use crate::game::player::Player;
+use crate::network::Channel;
+use crate::{NetworkMessages, NoraySet, PlayerRegistrationInfo};
+
+/// Marks an entity this peer spawned and replicates to the others. It gets a
+/// [`NetworkId`] and [`Authority`] if it has none, appears on every other
+/// peer, follows the changes its owner makes to its replicated components
+/// and is despawned there with it.
+///
+/// A `Player` is identified by its OID instead and its copies are spawned
+/// from `GameState`, so only its components are replicated.
+#[derive(Component, Debug, Default, Clone, Copy)]
+pub struct Replicated;
+
+/// Registers components for replication; see [`AppReplicateExt::replicate`].
+pub trait AppReplicateExt {
+    /// Sends `C` whenever it changes on a networked entity this peer has
+    /// [`Authority`] over and applies it to that entity's copy on the other
+    /// peers, inserting it if missing.
+    ///
+    /// `C` is serialized through reflection, so it needs
+    /// `#[reflect(Component)]` and either `FromReflect` or
+    /// `#[reflect(Default)]`. Every peer must register the same components
+    /// in the same order.
+    fn replicate<C>(&mut self) -> &mut Self
+    where
+        C: Component + Reflect + GetTypeRegistration;
+}
+
+impl AppReplicateExt for App {
+    fn replicate<C>(&mut self) -> &mut Self
+    where
+        C: Component + Reflect + GetTypeRegistration,
+    {
+        self.register_type::<C>()
+            .init_resource::<ReplicationRegistry>()
+            .init_resource::<OutgoingReplication>();
+
+        let registered = self
+            .world()
+            .resource::<AppTypeRegistry>()
+            .read()
+            .get_type_data::<ReflectComponent>(TypeId::of::<C>())
+            .is_some();
+        assert!(
+            registered,
+            "{} needs #[reflect(Component)] to be replicated",
+            std::any::type_name::<C>()
+        );
+
+        let mut registry = self.world_mut().resource_mut::<ReplicationRegistry>();
+        if registry.index_of(TypeId::of::<C>()).is_none() {
+            registry.components.push(TypeId::of::<C>());
+            self.add_systems(
+                Update,
+                collect_changed::<C>
+                    .before(send_replication)
+                    .in_set(NoraySet::Send),
+            );
+        }
+        self
+    }
+}
+
+/// Replicated component types, indexed by registration order on the wire.
+#[derive(Resource, Debug, Default)]
+pub struct ReplicationRegistry {
+    components: Vec<TypeId>,
+}
+
+impl ReplicationRegistry {
+    fn index_of(&self, type_id: TypeId) -> Option<u16> {
+        self.components
+            .iter()
+            .position(|id| *id == type_id)
+            .map(|index| index as u16)
+    }
+}
+
+/// Replication traffic from the peer `from`, carried on
+/// [`Channel::REPLICATION`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicationPacket {
+    pub from: String,
+    pub message: ReplicationMessage,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplicationMessage {
+    /// A new entity and all its replicated components.
+    Spawn(ReplicationUpdate),
+    /// Components of an entity that changed.
+    Update(ReplicationUpdate),
+    Despawn(NetworkId),
+    /// Asks `owner` for the [`Authority`] over `id`.
+    RequestAuthority {
+        id: NetworkId,
+        owner: String,
+    },
+    /// The sender handed its [`Authority`] over `id` to `owner`.
+    GrantAuthority {
+        id: NetworkId,
+        owner: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicationUpdate {
+    pub id: NetworkId,
+    /// Registry index and reflection-serialized value of each component.
+    pub components: Vec<(u16, Vec<u8>)>,
+}
+
+/// Replication collected this frame, sent by [`send_replication`].
+#[derive(Resource, Debug, Default)]
+pub struct OutgoingReplication {
+    entities: HashMap<NetworkId, OutgoingEntity>,
+    /// Sent after the updates, so an entity's last changes arrive before its
+    /// despawn or new owner.
+    messages: Vec<ReplicationMessage>,
+}
+
+impl OutgoingReplication {
+    pub fn push(&mut self, message: ReplicationMessage) {
+        self.messages.push(message);
+    }
+}
+
+#[derive(Debug, Default)]
+struct OutgoingEntity {
+    spawn: bool,
+    components: Vec<(u16, Vec<u8>)>,
+}
+
+/// Received packets not applied yet, and component updates for players
+/// whose copy hasn't been spawned yet (newest value per component).
+#[derive(Resource, Debug, Default)]
+pub struct PendingReplication {
+    packets: Vec<ReplicationPacket>,
+    players: HashMap<NetworkId, HashMap<u16, Vec<u8>>>,
+}
+
+impl PendingReplication {
+    pub fn push(&mut self, packet: ReplicationPacket) {
+        self.packets.push(packet);
+    }
+}
+
+fn codec() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+type UnidentifiedQuery<'w, 's> =
+    Query<'w, 's, Entity, (With<Replicated>, Without<NetworkId>, Without<Player>)>;
+
+/// Gives players and [`Replicated`] entities their [`NetworkId`] and
+/// [`Authority`], and maps every entity that has an id.
+pub fn assign_network_ids(
+    mut commands: Commands,
+    mut map: ResMut<NetworkEntityMap>,
+    registration: Res<PlayerRegistrationInfo>,
+    players: Query<(Entity, &Player), Without<NetworkId>>,
+    replicated: UnidentifiedQuery,
+    added: Query<(Entity, &NetworkId), Added<NetworkId>>,
+) {
+    for (entity, id) in added.iter() {
+        if map.entity(id) != Some(entity) {
+            map.insert(id.clone(), entity);
+        }
+    }
+
+    for (entity, player) in players.iter() {
+        let id = NetworkId::player(player.oid.clone());
+        map.insert(id.clone(), entity);
+        commands
+            .entity(entity)
+            .insert((id, Authority::new(player.oid.clone())));
+    }
+
+    for entity in replicated.iter() {
+        let id = map.allocate(&registration.oid);
+        map.insert(id.clone(), entity);
+        commands
+            .entity(entity)
+            .insert((id, Authority::new(registration.oid.clone())));
+    }
+}
+
+/// Forgets despawned entities, announcing the ones this peer has authority
+/// over.
+pub fn track_despawns(
+    mut removed: RemovedComponents<NetworkId>,
+    authorities: Query<(Entity, &Authority), Changed<Authority>>,
+    mut owned: Local<OwnedEntities>,
+    mut map: ResMut<NetworkEntityMap>,
+    registration: Res<PlayerRegistrationInfo>,
+    mut outgoing: ResMut<OutgoingReplication>,
+) {
+    for (entity, authority) in authorities.iter() {
+        owned.update(entity, authority, &registration.oid);
+    }
+
+    for entity in removed.read() {
+        if let Some(id) = map.remove_entity(entity)
+            && owned.remove(entity)
+        {
+            outgoing.push(ReplicationMessage::Despawn(id));
+        }
+    }
+}
+
+/// Despawns the entities of peers that left.
+pub fn despawn_departed_entities(
+    mut commands: Commands,
+    mut disconnected: EventReader<PeerDisconnected>,
+    map: Res<NetworkEntityMap>,
+    mut pending: ResMut<PendingReplication>,
+) {
+    for event in disconnected.read() {
+        // Players are despawned by `despawn_disconnected_players`.
+        for entity in map.owned_by(&event.oid) {
+            if map.network_id(entity).is_some_and(|id| !id.is_player()) {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        pending.players.retain(|id, _| id.owner != event.oid);
+    }
+}
+
+/// Marks newly identified [`Replicated`] entities to be sent as spawns.
+pub fn collect_spawned(
+    spawned: Query<&NetworkId, (With<Replicated>, Added<NetworkId>)>,
+    mut outgoing: ResMut<OutgoingReplication>,
+) {
+    for id in spawned.iter() {
+        outgoing.entities.entry(id.clone()).or_default().spawn = true;
+    }
+}
+
+type ChangedQuery<'w, 's, C> =
+    Query<'w, 's, (&'static NetworkId, &'static Authority, &'static C), Changed<C>>;
+
+fn collect_changed<C: Component + Reflect>(
+    changed: ChangedQuery<C>,
+    registration: Res<PlayerRegistrationInfo>,
+    replication: Res<ReplicationRegistry>,
+    types: Res<AppTypeRegistry>,
+    mut outgoing: ResMut<OutgoingReplication>,
+) {
+    let Some(index) = replication.index_of(TypeId::of::<C>()) else {
+        return;
+    };
+    let types = types.read();
+
+    for (id, authority, component) in changed.iter() {
+        if !authority.is_owned_by(&registration.oid) {
+            continue;
+        }
+
+        let serializer = TypedReflectSerializer::new(component.as_reflect(), &types);
+        match codec().serialize(&serializer) {
+            Ok(bytes) => outgoing
+                .entities
+                .entry(id.clone())
+                .or_default()
+                .components
+                .push((index, bytes)),
+            Err(e) => warn!(
+                component = std::any::type_name::<C>(),
+                error = %e,
+                "Failed to serialize"
+            ),
+        }
+    }
+}
+
+/// Sends one message per spawned or changed entity, then the queued ones.
+pub fn send_replication(
+    mut outgoing: ResMut<OutgoingReplication>,
+    registration: Res<PlayerRegistrationInfo>,
+    messages: Option<Res<NetworkMessages>>,
+) {
+    let outgoing = &mut *outgoing;
+    let updates = outgoing.entities.drain().map(|(id, entity)| {
+        let update = ReplicationUpdate {
+            id,
+            components: entity.components,
+        };
+        if entity.spawn {
+            ReplicationMessage::Spawn(update)
+        } else {
+            ReplicationMessage::Update(update)
+        }
+    });
+    let queued = outgoing.messages.drain(..);
+
+    for message in updates.chain(queued) {
+        let Some(messages) = &messages else {
+            continue;
+        };
+
+        let packet = ReplicationPacket {
+            from: registration.oid.clone(),
+            message,
+        };
+        match bincode::serialize(&packet) {
+            Ok(payload) => messages.send(Channel::REPLICATION, payload),
+            Err(e) => warn!(error = %e, "Failed to encode replication message"),
+        }
+    }
+}
+
+/// Applies received replication from the peers with [`Authority`]: spawns
+/// copies of new entities, updates and despawns known ones, hands authority
+/// over, and holds player updates until the player's copy exists.
+pub fn apply_replication(world: &mut World) {
+    let packets = std::mem::take(&mut world.resource_mut::<PendingReplication>().packets);
+    if packets.is_empty() && world.resource::<PendingReplication>().players.is_empty() {
+        return;
+    }
+
+    let own_oid = world.resource::<PlayerRegistrationInfo>().oid.clone();
+
+    world.resource_scope(|world, mut map: Mut<NetworkEntityMap>| {
+        for ReplicationPacket { from, message } in packets {
+            if from == own_oid {
+                continue;
+            }
+
+            match message {
+                ReplicationMessage::Spawn(update) => {
+                    apply_update(world, &mut map, &from, update, true);
+                }
+                ReplicationMessage::Update(update) => {
+                    apply_update(world, &mut map, &from, update, false);
+                }
+                ReplicationMessage::Despawn(id) => {
+                    if id.owner == from {
+                        world
+                            .resource_mut::<PendingReplication>()
+                            .players
+                            .remove(&id);
+                    }
+                    if let Some(entity) = map.entity(&id)
+                        && has_authority(world, entity, &from)
+                    {
+                        map.remove_entity(entity);
+                        despawn_with_children_recursive(world, entity);
+                    }
+                }
+                ReplicationMessage::RequestAuthority { id, owner } => {
+                    authority::grant_authority(world, &map, &from, &id, &owner);
+                }
+                ReplicationMessage::GrantAuthority { id, owner } => {
+                    authority::transfer(world, &map, &from, &id, &owner);
+                }
+            }
+        }
+
+        let ready: Vec<(Entity, HashMap<u16, Vec<u8>>)> = {
+            let mut pending = world.resource_mut::<PendingReplication>();
+            let ids: Vec<NetworkId> = pending
+                .players
+                .keys()
+                .filter(|id| map.entity(id).is_some())
+                .cloned()
+                .collect();
+            ids.into_iter()
+                .filter_map(|id| Some((map.entity(&id)?, pending.players.remove(&id)?)))
+                .collect()
+        };
+
+        for (entity, components) in ready {
+            apply_components(world, entity, components);
+        }
+    });
+}
+
+fn has_authority(world: &World, entity: Entity, oid: &str) -> bool {
+    world
+        .get::<Authority>(entity)
+        .is_some_and(|authority| authority.is_owned_by(oid))
+}
+
+fn apply_update(
+    world: &mut World,
+    map: &mut NetworkEntityMap,
+    from: &str,
+    update: ReplicationUpdate,
+    spawn: bool,
+) {
+    let entity = match map.entity(&update.id) {
+        Some(entity) => entity,
+        None if update.id.is_player() => {
+            if update.id.owner == from {
+                world
+                    .resource_mut::<PendingReplication>()
+                    .players
+                    .entry(update.id)
+                    .or_default()
+                    .extend(update.components);
+            }
+            return;
+        }
+        None if spawn && update.id.owner == from => {
+            let entity = world.spawn((update.id.clone(), Authority::new(from))).id();
+            map.insert(update.id.clone(), entity);
+            entity
+        }
+        // Already despawned here; the update arrived late.
+        None => return,
+    };
+
+    if !has_authority(world, entity, from) {
+        debug!(id = %update.id, from, "Ignoring update from a non-owner");
+        return;
+    }
+
+    apply_components(world, entity, update.components);
+}
+
+fn apply_components(
+    world: &mut World,
+    entity: Entity,
+    components: impl IntoIterator<Item = (u16, Vec<u8>)>,
+) {
+    let registered = world.resource::<ReplicationRegistry>().components.clone();
+    let types = world.resource::<AppTypeRegistry>().clone();
+    let types = types.read();
+
+    for (index, bytes) in components {
+        let Some(type_id) = registered.get(index as usize) else {
+            warn!(index, "Unknown replicated component");
+            continue;
+        };
+        let Some(mut entity) = world.get_entity_mut(entity) else {
+            return;
+        };
+
+        match deserialize(&types, *type_id, &bytes) {
+            Some((reflect_component, value)) => {
+                reflect_component.apply_or_insert(&mut entity, &*value, &types);
+            }
+            None => warn!(index, "Failed to decode replicated component"),
+        }
+    }
+}
+
+fn deserialize<'a>(
+    types: &'a TypeRegistry,
+    type_id: TypeId,
+    bytes: &[u8],
+) -> Option<(&'a ReflectComponent, Box<dyn Reflect>)> {
+    let registration = types.get(type_id)?;
+    let reflect_component = registration.data::<ReflectComponent>()?;
+    let value = codec()
+        .deserialize_seed(TypedReflectDeserializer::new(registration, types), bytes)
+        .ok()?;
+    Some((reflect_component, value))
+}
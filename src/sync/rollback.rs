@@ -0,0 +1,302 @@
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, Sender};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+use super::RemotePlayerData;
+use super::receive::PeerConnected;
+use crate::game::local_input::LocalInputLatch;
+use crate::game::player::{IsJumping, Player, SPAWN_POSITION, Velocity, simulate, spawn_player};
+use crate::local_player_data::LocalPlayerMarker;
+use crate::network::{PlayerInput, RollbackInput};
+use crate::{PlayerRegistrationInfo, SimulationTick};
+
+/// Tuning of the rollback mode.
+#[derive(Resource, Debug, Clone)]
+pub struct RollbackConfig {
+    /// Furthest back in frames a late input can still be corrected.
+    pub max_rollback_frames: u32,
+    /// Recent local frames repeated in every input message.
+    pub input_redundancy: u32,
+}
+
+impl Default for RollbackConfig {
+    fn default() -> Self {
+        Self {
+            max_rollback_frames: 16,
+            input_redundancy: 8,
+        }
+    }
+}
+
+/// Session channels used in rollback mode.
+#[derive(Resource)]
+pub struct RollbackChannels {
+    pub tx: Sender<RollbackInput>,
+    pub rx: Receiver<RollbackInput>,
+}
+
+/// State of one player at the start of a frame.
+#[derive(Clone, Copy)]
+struct PlayerSnapshot {
+    entity: Entity,
+    transform: Transform,
+    velocity: Velocity,
+    is_jumping: IsJumping,
+}
+
+#[derive(Debug, Default)]
+struct RemoteInputs {
+    confirmed: BTreeMap<u32, PlayerInput>,
+    /// Guesses used for frames whose input had not arrived yet.
+    predicted: BTreeMap<u32, PlayerInput>,
+}
+
+impl RemoteInputs {
+    /// The confirmed input for `frame`, or otherwise a repeat of the newest
+    /// earlier one without its jump.
+    fn input_for(&mut self, frame: u32, dt: f32) -> PlayerInput {
+        if let Some(input) = self.confirmed.get(&frame) {
+            return *input;
+        }
+
+        let predicted = self
+            .confirmed
+            .range(..frame)
+            .next_back()
+            .map(|(_, input)| PlayerInput {
+                jump: false,
+                ..*input
+            })
+            .unwrap_or(PlayerInput {
+                dt,
+                ..Default::default()
+            });
+
+        self.predicted.insert(frame, predicted);
+        predicted
+    }
+}
+
+/// Frame counter, input history and world snapshots of a rollback session.
+///
+/// Frames are numbered by the shared [`SimulationTick`], so frame N means the
+/// same moment on every peer.
+#[derive(Resource, Default)]
+pub struct RollbackSession {
+    frame: u32,
+    local: BTreeMap<u32, PlayerInput>,
+    remote: HashMap<String, RemoteInputs>,
+    snapshots: VecDeque<(u32, Vec<PlayerSnapshot>)>,
+}
+
+impl RollbackSession {
+    /// The next frame to be simulated.
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// Stores remote inputs and returns the earliest already simulated frame
+    /// that was predicted wrongly, if any.
+    fn receive(&mut self, message: RollbackInput) -> Option<u32> {
+        let remote = self.remote.entry(message.oid).or_default();
+        let mut mispredicted = None;
+
+        for (frame, input) in (message.first_frame..).zip(message.inputs) {
+            if remote.confirmed.insert(frame, input).is_some() {
+                continue;
+            }
+
+            if let Some(predicted) = remote.predicted.remove(&frame)
+                && predicted != input
+                && frame < self.frame
+            {
+                mispredicted =
+                    Some(mispredicted.map_or(frame, |earliest: u32| earliest.min(frame)));
+            }
+        }
+
+        mispredicted
+    }
+
+    /// Forgets history older than the rollback window.
+    fn prune(&mut self, config: &RollbackConfig) {
+        let window = config.max_rollback_frames.max(config.input_redundancy);
+        let oldest = self.frame.saturating_sub(window);
+
+        self.local = self.local.split_off(&oldest);
+        for remote in self.remote.values_mut() {
+            remote.confirmed = remote.confirmed.split_off(&oldest);
+            remote.predicted = remote.predicted.split_off(&oldest);
+        }
+
+        while self.snapshots.len() > config.max_rollback_frames as usize {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// The message carrying the most recent local inputs.
+    fn outgoing(&self, oid: &str, config: &RollbackConfig) -> RollbackInput {
+        let first_frame = self.frame.saturating_sub(config.input_redundancy);
+
+        RollbackInput {
+            oid: oid.to_string(),
+            first_frame,
+            inputs: (first_frame..self.frame)
+                .map(|frame| self.local.get(&frame).copied().unwrap_or_default())
+                .collect(),
+        }
+    }
+}
+
+type RollbackPlayerQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Player,
+        &'static mut Transform,
+        &'static mut Velocity,
+        &'static mut IsJumping,
+    ),
+>;
+
+/// Runs one fixed frame: applies late remote inputs by rolling back and
+/// re-simulating, then simulates the frame of the current [`SimulationTick`]
+/// for every player and sends the local input to the other peers.
+///
+/// When the tick jumps ahead, e.g. when a joiner first follows the host's,
+/// the skipped frames are not simulated. When it jumps back, nothing new is
+/// simulated until it reaches the frames not simulated yet.
+#[allow(clippy::too_many_arguments)]
+pub fn advance_rollback(
+    mut commands: Commands,
+    mut session: ResMut<RollbackSession>,
+    tick: Res<SimulationTick>,
+    latch: Res<LocalInputLatch>,
+    config: Res<RollbackConfig>,
+    channels: Res<RollbackChannels>,
+    registration: Res<PlayerRegistrationInfo>,
+    mut remote_data: ResMut<RemotePlayerData>,
+    time: Res<Time>,
+    mut connected: EventWriter<PeerConnected>,
+    mut players: RollbackPlayerQuery,
+) {
+    let dt = time.delta_seconds();
+    let now = time.elapsed_seconds_f64();
+    let mut rollback_to: Option<u32> = None;
+    let mut spawned = HashSet::new();
+
+    for message in channels.rx.try_iter() {
+        if message.oid == registration.oid || remote_data.departed.contains(&message.oid) {
+            continue;
+        }
+
+        if remote_data
+            .last_seen
+            .insert(message.oid.clone(), now)
+            .is_none()
+        {
+            connected.send(PeerConnected {
+                oid: message.oid.clone(),
+            });
+        }
+
+        if !players
+            .iter()
+            .any(|(_, player, ..)| player.oid == message.oid)
+            && spawned.insert(message.oid.clone())
+        {
+            spawn_player(
+                &mut commands,
+                message.oid.clone(),
+                false,
+                SPAWN_POSITION,
+                Color::srgb(1.0, 0.0, 0.0),
+            );
+        }
+
+        if let Some(frame) = session.receive(message) {
+            rollback_to = Some(rollback_to.map_or(frame, |earliest| earliest.min(frame)));
+        }
+    }
+
+    if let Some(frame) = rollback_to {
+        let current = session.frame;
+
+        match session.snapshots.iter().position(|(at, _)| *at == frame) {
+            Some(index) => {
+                for snapshot in &session.snapshots[index].1 {
+                    if let Ok((_, _, mut transform, mut velocity, mut is_jumping)) =
+                        players.get_mut(snapshot.entity)
+                    {
+                        *transform = snapshot.transform;
+                        *velocity = snapshot.velocity;
+                        *is_jumping = snapshot.is_jumping;
+                    }
+                }
+
+                session.snapshots.truncate(index);
+                for frame in frame..current {
+                    simulate_frame(&mut session, frame, dt, &registration.oid, &mut players);
+                }
+            }
+            None => warn!(frame, current, "Remote input arrived too late to roll back"),
+        }
+    }
+
+    let frame = tick.0;
+    if frame < session.frame {
+        return;
+    }
+
+    session.frame = frame;
+    session.local.insert(frame, latch.input(dt));
+    simulate_frame(&mut session, frame, dt, &registration.oid, &mut players);
+    session.frame = frame + 1;
+
+    let _ = channels
+        .tx
+        .send(session.outgoing(&registration.oid, &config));
+    session.prune(&config);
+}
+
+/// Snapshots every player, then advances it by its input for `frame`.
+fn simulate_frame(
+    session: &mut RollbackSession,
+    frame: u32,
+    dt: f32,
+    own_oid: &str,
+    players: &mut RollbackPlayerQuery,
+) {
+    let snapshot = players
+        .iter()
+        .map(
+            |(entity, _, transform, velocity, is_jumping)| PlayerSnapshot {
+                entity,
+                transform: *transform,
+                velocity: *velocity,
+                is_jumping: *is_jumping,
+            },
+        )
+        .collect();
+    session.snapshots.push_back((frame, snapshot));
+
+    for (_, player, mut transform, mut velocity, mut is_jumping) in players.iter_mut() {
+        let input = if player.oid == own_oid {
+            session.local.get(&frame).copied().unwrap_or_default()
+        } else {
+            session
+                .remote
+                .entry(player.oid.clone())
+                .or_default()
+                .input_for(frame, dt)
+        };
+
+        simulate(&mut transform, &mut velocity, &mut is_jumping, &input);
+    }
+}
+
+/// Whether the local player exists, so fixed frames have someone to drive.
+pub fn has_local_player(players: Query<(), With<LocalPlayerMarker>>) -> bool {
+    !players.is_empty()
+}
@@ -0,0 +1,155 @@
+use bevy::diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic};
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::network::{ConnectionStats, PeerStats};
+
+/// How often bandwidth is recomputed and diagnostics are measured.
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The running session's traffic counters.
+#[derive(Resource)]
+pub struct SessionStats(pub ConnectionStats);
+
+/// One peer's [`PeerStats`] plus its recent bandwidth.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PeerNetworkStats {
+    pub totals: PeerStats,
+    pub bytes_sent_per_second: f64,
+    pub bytes_received_per_second: f64,
+}
+
+/// Latency, loss and bandwidth of every peer of the session.
+///
+/// Aggregates over all peers are also published to Bevy's `DiagnosticsStore`
+/// under the `noray/` paths below.
+#[derive(Resource, Debug, Default)]
+pub struct NetworkStats {
+    peers: HashMap<SocketAddr, PeerNetworkStats>,
+    routes: HashMap<String, SocketAddr>,
+    /// Totals at the last bandwidth computation, and when it happened.
+    window_start: Option<(Duration, HashMap<SocketAddr, PeerStats>)>,
+}
+
+impl NetworkStats {
+    /// Mean round-trip time over all peers, in milliseconds.
+    pub const RTT: DiagnosticPath = DiagnosticPath::const_new("noray/rtt");
+    /// Mean jitter over all peers, in milliseconds.
+    pub const JITTER: DiagnosticPath = DiagnosticPath::const_new("noray/jitter");
+    /// Share of pings lost over all peers, in percent.
+    pub const PACKET_LOSS: DiagnosticPath = DiagnosticPath::const_new("noray/packet_loss");
+    pub const BYTES_SENT: DiagnosticPath = DiagnosticPath::const_new("noray/bytes_sent");
+    pub const BYTES_RECEIVED: DiagnosticPath = DiagnosticPath::const_new("noray/bytes_received");
+
+    pub fn peer(&self, peer: SocketAddr) -> Option<&PeerNetworkStats> {
+        self.peers.get(&peer)
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = (&SocketAddr, &PeerNetworkStats)> {
+        self.peers.iter()
+    }
+
+    /// The peer whose link carries player `oid`'s packets: the player's own
+    /// address on the host, the host's on joiners.
+    pub fn peer_of(&self, oid: &str) -> Option<SocketAddr> {
+        self.routes.get(oid).copied()
+    }
+
+    /// Takes new totals; bandwidth is recomputed once `STATS_INTERVAL` has
+    /// passed since the last time. Returns whether it was.
+    pub fn update(&mut self, now: Duration, totals: HashMap<SocketAddr, PeerStats>) -> bool {
+        let recompute = match &self.window_start {
+            Some((start, _)) => now.saturating_sub(*start) >= STATS_INTERVAL,
+            None => true,
+        };
+
+        if recompute {
+            if let Some((start, previous)) = &self.window_start {
+                let elapsed = (now - *start).as_secs_f64();
+
+                for (peer, stats) in &totals {
+                    let before = previous.get(peer).copied().unwrap_or_default();
+                    let entry = self.peers.entry(*peer).or_default();
+                    entry.bytes_sent_per_second =
+                        (stats.bytes_sent - before.bytes_sent) as f64 / elapsed;
+                    entry.bytes_received_per_second =
+                        (stats.bytes_received - before.bytes_received) as f64 / elapsed;
+                }
+            }
+            self.window_start = Some((now, totals.clone()));
+        }
+
+        for (peer, stats) in totals {
+            self.peers.entry(peer).or_default().totals = stats;
+        }
+
+        recompute
+    }
+
+    fn mean(&self, value: impl Fn(&PeerNetworkStats) -> f64) -> f64 {
+        if self.peers.is_empty() {
+            return 0.0;
+        }
+        self.peers.values().map(value).sum::<f64>() / self.peers.len() as f64
+    }
+
+    fn loss(&self) -> f64 {
+        let (lost, total) = self.peers.values().fold((0, 0), |(lost, total), peer| {
+            (
+                lost + peer.totals.pings_lost,
+                total + peer.totals.pings_lost + peer.totals.pings_answered,
+            )
+        });
+
+        if total == 0 {
+            0.0
+        } else {
+            lost as f64 / total as f64
+        }
+    }
+}
+
+pub fn register_network_diagnostics(app: &mut App) {
+    app.register_diagnostic(Diagnostic::new(NetworkStats::RTT).with_suffix("ms"))
+        .register_diagnostic(Diagnostic::new(NetworkStats::JITTER).with_suffix("ms"))
+        .register_diagnostic(Diagnostic::new(NetworkStats::PACKET_LOSS).with_suffix("%"))
+        .register_diagnostic(Diagnostic::new(NetworkStats::BYTES_SENT).with_suffix("B/s"))
+        .register_diagnostic(Diagnostic::new(NetworkStats::BYTES_RECEIVED).with_suffix("B/s"));
+}
+
+pub fn update_network_stats(
+    time: Res<Time<Real>>,
+    session: Res<SessionStats>,
+    mut stats: ResMut<NetworkStats>,
+    mut diagnostics: Diagnostics,
+) {
+    stats.routes = session.0.routes();
+
+    if !stats.update(time.elapsed(), session.0.snapshot()) {
+        return;
+    }
+
+    diagnostics.add_measurement(&NetworkStats::RTT, || {
+        stats.mean(|peer| peer.totals.rtt.as_secs_f64() * 1000.0)
+    });
+    diagnostics.add_measurement(&NetworkStats::JITTER, || {
+        stats.mean(|peer| peer.totals.jitter.as_secs_f64() * 1000.0)
+    });
+    diagnostics.add_measurement(&NetworkStats::PACKET_LOSS, || stats.loss() * 100.0);
+    diagnostics.add_measurement(&NetworkStats::BYTES_SENT, || {
+        stats
+            .peers
+            .values()
+            .map(|peer| peer.bytes_sent_per_second)
+            .sum()
+    });
+    diagnostics.add_measurement(&NetworkStats::BYTES_RECEIVED, || {
+        stats
+            .peers
+            .values()
+            .map(|peer| peer.bytes_received_per_second)
+            .sum()
+    });
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_noray::sync::{
+    Authority, NetworkId, PendingReplication, Replicated, ReplicationMessage, ReplicationPacket,
+    RequestAuthority,
+};
+use bevy_noray::{NorayPlugin, PlayerRegistrationInfo};
+use common::{Health, MockNoray, app, in_game, oid, update_until};
+
+/// The entity with `id` and its authority and health on `app`.
+fn networked(app: &mut App, id: &NetworkId) -> Option<(Entity, Authority, Health)> {
+    app.world_mut()
+        .query::<(Entity, &NetworkId, &Authority, &Health)>()
+        .iter(app.world())
+        .find(|(_, other, _, _)| *other == id)
+        .map(|(entity, _, authority, health)| (entity, authority.clone(), *health))
+}
+
+#[test]
+fn ownership_moves_to_the_requesting_peer() {
+    let noray = MockNoray::start();
+    let mut host = app(NorayPlugin::host(noray.config(), 2));
+    update_until(&mut [&mut host], |apps| {
+        apps[0]
+            .world()
+            .contains_resource::<PlayerRegistrationInfo>()
+    });
+    let host_oid = oid(&host);
+    let mut joiner = app(NorayPlugin::join(noray.config(), host_oid.clone()));
+    update_until(&mut [&mut host, &mut joiner], |apps| {
+        in_game(apps[0]) && in_game(apps[1])
+    });
+    let joiner_oid = oid(&joiner);
+
+    let ball = host.world_mut().spawn((Replicated, Health(1))).id();
+    update_until(&mut [&mut host, &mut joiner], |apps| {
+        apps[0].world().get::<NetworkId>(ball).is_some()
+    });
+    let id = host.world().get::<NetworkId>(ball).unwrap().clone();
+    update_until(&mut [&mut host, &mut joiner], |apps| {
+        networked(apps[1], &id).is_some()
+    });
+    let (copy, authority, _) = networked(&mut joiner, &id).unwrap();
+    assert_eq!(authority, Authority::new(host_oid.clone()));
+
+    // Only the owner's changes are replicated.
+    joiner.world_mut().get_mut::<Health>(copy).unwrap().0 = 50;
+    host.world_mut().get_mut::<Health>(ball).unwrap().0 = 2;
+    update_until(&mut [&mut host, &mut joiner], |apps| {
+        networked(apps[1], &id).unwrap().2 == Health(2)
+    });
+
+    joiner
+        .world_mut()
+        .send_event(RequestAuthority { entity: copy });
+    update_until(&mut [&mut host, &mut joiner], |apps| {
+        networked(apps[0], &id).unwrap().1.is_owned_by(&joiner_oid)
+            && networked(apps[1], &id).unwrap().1.is_owned_by(&joiner_oid)
+    });
+
+    joiner.world_mut().get_mut::<Health>(copy).unwrap().0 = 9;
+    update_until(&mut [&mut host, &mut joiner], |apps| {
+        networked(apps[0], &id).unwrap().2 == Health(9)
+    });
+
+    // Claims from peers without authority are ignored.
+    joiner
+        .world_mut()
+        .resource_mut::<PendingReplication>()
+        .push(ReplicationPacket {
+            from: host_oid.clone(),
+            message: ReplicationMessage::GrantAuthority {
+                id: id.clone(),
+                owner: host_oid.clone(),
+            },
+        });
+    joiner.update();
+    assert!(
+        networked(&mut joiner, &id)
+            .unwrap()
+            .1
+            .is_owned_by(&joiner_oid)
+    );
+}
@@ -0,0 +1,38 @@
+use std::time::{Duration, Instant};
+
+use bevy_noray::network::ClockSample;
+use bevy_noray::sync::NetworkTime;
+
+const TICK_RATE: f64 = 60.0;
+
+fn sample(remote_tick: u32, remote_tick_at: Instant) -> ClockSample {
+    ClockSample {
+        peer: "127.0.0.1:9000".parse().unwrap(),
+        rtt: Duration::from_millis(40),
+        offset: 0.25,
+        remote_tick,
+        remote_tick_at,
+    }
+}
+
+#[test]
+fn joiner_extrapolates_the_host_tick_from_the_newest_pong() {
+    let mut time = NetworkTime::new(TICK_RATE, false);
+    let now = Instant::now();
+    assert_eq!(time.host_tick_at(now), None);
+
+    time.record(sample(100, now - Duration::from_millis(500)));
+
+    let host_tick = time.host_tick_at(now).unwrap();
+    assert!((host_tick - 130.0).abs() < 1e-6);
+    assert_eq!(
+        time.rtt("127.0.0.1:9000".parse().unwrap()),
+        Some(Duration::from_millis(40))
+    );
+}
+
+#[test]
+fn host_reports_its_own_tick() {
+    let time = NetworkTime::new(TICK_RATE, true);
+    assert_eq!(time.host_tick(), Some(0.0));
+}
@@ -0,0 +1,400 @@
+//! In-process stand-in for the noray server, plus helpers shared by the
+//! tests that drive apps against it.
+//!
+//! The mock implements the subset of noray the client relies on:
+//! `register-host`, UDP pid registration, `connect`, `connect-relay` and relay
+//! forwarding, all on ephemeral localhost ports.
+
+// Every test binary compiles this module but uses only part of it.
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+use bevy_noray::network::NorayConfig;
+use bevy_noray::network::handshake::{self, HandshakeProgress, NoraySession};
+use bevy_noray::sync::AppReplicateExt;
+use bevy_noray::{NorayPlugin, NorayState, PlayerRegistrationInfo};
+
+/// How long a test waits for the network before failing.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A replicated component for tests to change.
+#[derive(Component, Reflect, Default, Debug, Clone, Copy, PartialEq)]
+#[reflect(Component, Default)]
+pub struct Health(pub u32);
+
+/// A headless app running `noray` and replicating [`Health`].
+pub fn app(noray: NorayPlugin) -> App {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, StatesPlugin))
+        .add_plugins(noray)
+        .replicate::<Health>();
+    app
+}
+
+/// Updates every app until `done` holds, failing after [`TIMEOUT`].
+pub fn update_until(apps: &mut [&mut App], done: impl Fn(&mut [&mut App]) -> bool) {
+    let deadline = Instant::now() + TIMEOUT;
+    while !done(apps) {
+        assert!(Instant::now() < deadline, "timed out");
+        for app in apps.iter_mut() {
+            app.update();
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+pub fn state(app: &App) -> NorayState {
+    *app.world()
+        .resource::<bevy::prelude::State<NorayState>>()
+        .get()
+}
+
+pub fn in_game(app: &App) -> bool {
+    state(app) == NorayState::InGame
+}
+
+/// The OID `app` registered with noray.
+pub fn oid(app: &App) -> String {
+    app.world().resource::<PlayerRegistrationInfo>().oid.clone()
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+struct Host {
+    oid: String,
+    pid: String,
+    /// Control connection, used to push replies to this host.
+    stream: TcpStream,
+    /// UDP address registered with the pid.
+    udp: Option<SocketAddr>,
+}
+
+/// A relay port allocated for one UDP address.
+struct Relay {
+    address: SocketAddr,
+    socket: Arc<UdpSocket>,
+}
+
+#[derive(Default)]
+struct State {
+    hosts: Vec<Host>,
+    relays: Vec<Relay>,
+    /// Handed out by `connect` instead of the peers' addresses when punching
+    /// through should fail. Nothing ever reads from it.
+    unreachable: Option<UdpSocket>,
+    /// Welcomes the relay still has to lose.
+    lost_welcomes: usize,
+}
+
+type Shared = Arc<Mutex<State>>;
+
+/// A running mock noray server; stops when dropped.
+pub struct MockNoray {
+    tcp_port: u16,
+    udp_port: u16,
+    stop: Arc<AtomicBool>,
+}
+
+impl MockNoray {
+    pub fn start() -> Self {
+        Self::launch(State::default())
+    }
+
+    /// A server whose peers can never punch through to each other, as if
+    /// behind symmetric NATs, so only the relay works.
+    pub fn without_punch_through() -> Self {
+        Self::launch(State {
+            unreachable: Some(UdpSocket::bind("127.0.0.1:0").expect("Failed to bind mock UDP")),
+            ..State::default()
+        })
+    }
+
+    /// Like [`MockNoray::without_punch_through`], but the relay loses the
+    /// host's first welcome.
+    pub fn losing_first_welcome() -> Self {
+        Self::launch(State {
+            unreachable: Some(UdpSocket::bind("127.0.0.1:0").expect("Failed to bind mock UDP")),
+            lost_welcomes: 1,
+            ..State::default()
+        })
+    }
+
+    fn launch(state: State) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock TCP");
+        listener.set_nonblocking(true).unwrap();
+        let registrar = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind mock UDP");
+        registrar.set_read_timeout(Some(POLL_INTERVAL)).unwrap();
+
+        let tcp_port = listener.local_addr().unwrap().port();
+        let udp_port = registrar.local_addr().unwrap().port();
+        let stop = Arc::new(AtomicBool::new(false));
+        let state = Arc::new(Mutex::new(state));
+        let ids = Arc::new(AtomicUsize::new(0));
+
+        {
+            let state = state.clone();
+            let stop = stop.clone();
+            thread::spawn(move || accept(listener, state, ids, stop));
+        }
+        {
+            let state = state.clone();
+            let stop = stop.clone();
+            thread::spawn(move || register_udp(registrar, state, stop));
+        }
+
+        Self {
+            tcp_port,
+            udp_port,
+            stop,
+        }
+    }
+
+    pub fn config(&self) -> NorayConfig {
+        NorayConfig {
+            host: "127.0.0.1".to_string(),
+            tcp_port: self.tcp_port,
+            udp_port: self.udp_port,
+            ..NorayConfig::default()
+        }
+    }
+}
+
+/// Runs the host and joiner handshakes of a two player game against
+/// `config`, returning the host's session and the joiner's.
+pub async fn session_pair(config: NorayConfig) -> (NoraySession, NoraySession) {
+    let (host_progress, host_updates) = crossbeam_channel::unbounded();
+    let host = tokio::spawn(handshake::host(config.clone(), 2, host_progress));
+
+    let host_oid = loop {
+        match host_updates.recv_timeout(TIMEOUT).unwrap() {
+            HandshakeProgress::Registered(registration) => break registration.oid,
+            _ => continue,
+        }
+    };
+
+    let (join_progress, _join_updates) = crossbeam_channel::unbounded();
+    let joiner = handshake::join(config, host_oid, join_progress)
+        .await
+        .unwrap();
+
+    (host.await.unwrap().unwrap(), joiner)
+}
+
+impl Drop for MockNoray {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn accept(listener: TcpListener, state: Shared, ids: Arc<AtomicUsize>, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let state = state.clone();
+                let ids = ids.clone();
+                let stop = stop.clone();
+                thread::spawn(move || serve(stream, state, ids, stop));
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(_) => return,
+        }
+    }
+}
+
+/// Handles the commands of a single TCP client.
+fn serve(stream: TcpStream, state: Shared, ids: Arc<AtomicUsize>, stop: Arc<AtomicBool>) {
+    stream.set_nonblocking(false).unwrap();
+    stream.set_read_timeout(Some(POLL_INTERVAL)).unwrap();
+    let peer = stream.peer_addr().unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut line = String::new();
+
+    while !stop.load(Ordering::Relaxed) {
+        match reader.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {
+                handle_command(line.trim(), &stream, &state, &ids, &stop);
+                line.clear();
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => break,
+        }
+    }
+
+    // Like noray, forget hosts whose control connection is gone.
+    state
+        .lock()
+        .unwrap()
+        .hosts
+        .retain(|host| host.stream.peer_addr().ok() != Some(peer));
+}
+
+fn handle_command(
+    line: &str,
+    stream: &TcpStream,
+    shared: &Shared,
+    ids: &AtomicUsize,
+    stop: &Arc<AtomicBool>,
+) {
+    let (name, data) = line.split_once(' ').unwrap_or((line, ""));
+    let mut state = shared.lock().unwrap();
+
+    match name {
+        "register-host" => {
+            let id = ids.fetch_add(1, Ordering::Relaxed);
+            let host = Host {
+                oid: format!("mock-oid-{}", id),
+                pid: format!("mock-pid-{}", id),
+                stream: stream.try_clone().unwrap(),
+                udp: None,
+            };
+            send(stream, &format!("set-oid {}", host.oid));
+            send(stream, &format!("set-pid {}", host.pid));
+            state.hosts.push(host);
+        }
+        "connect" | "connect-relay" => {
+            let client = stream.peer_addr().ok();
+            let target = state.hosts.iter().position(|host| host.oid == data);
+            let source = state
+                .hosts
+                .iter()
+                .position(|host| host.stream.peer_addr().ok() == client);
+
+            let (target, source) = match (target, source) {
+                (Some(target), Some(source)) => (target, source),
+                (None, _) => {
+                    return send(stream, &format!("{} Unknown host oid: {}", name, data));
+                }
+                (_, None) => {
+                    return send(stream, &format!("{} Unknown client from address", name));
+                }
+            };
+
+            let (Some(target_udp), Some(source_udp)) =
+                (state.hosts[target].udp, state.hosts[source].udp)
+            else {
+                return send(
+                    stream,
+                    &format!("{} Host has no remote info registered!", name),
+                );
+            };
+
+            let (to_client, to_host) = if name == "connect" {
+                match &state.unreachable {
+                    Some(socket) => {
+                        let address = socket.local_addr().unwrap().to_string();
+                        (address.clone(), address)
+                    }
+                    None => (target_udp.to_string(), source_udp.to_string()),
+                }
+            } else {
+                (
+                    relay_port(&mut state, shared, stop, target_udp).to_string(),
+                    relay_port(&mut state, shared, stop, source_udp).to_string(),
+                )
+            };
+
+            send(stream, &format!("{} {}", name, to_client));
+            send(
+                &state.hosts[target].stream,
+                &format!("{} {}", name, to_host),
+            );
+        }
+        _ => send(stream, &format!("{} Unknown command", name)),
+    }
+}
+
+fn send(mut stream: &TcpStream, line: &str) {
+    let _ = stream.write_all(format!("{}\n", line).as_bytes());
+}
+
+/// Records the UDP address a pid is sent from, replying `OK`.
+fn register_udp(socket: UdpSocket, state: Shared, stop: Arc<AtomicBool>) {
+    let mut buf = [0u8; 1024];
+
+    while !stop.load(Ordering::Relaxed) {
+        let Ok((len, from)) = socket.recv_from(&mut buf) else {
+            continue;
+        };
+
+        let pid = String::from_utf8_lossy(&buf[..len]);
+        let mut state = state.lock().unwrap();
+
+        let reply = match state.hosts.iter_mut().find(|host| host.pid == pid) {
+            Some(host) => {
+                host.udp.get_or_insert(from);
+                "OK"
+            }
+            None => "Unknown host pid!",
+        };
+
+        let _ = socket.send_to(reply.as_bytes(), from);
+    }
+}
+
+/// Returns the relay port for `address`, allocating one on first use.
+///
+/// A packet from a registered address arriving on another address's relay
+/// port is forwarded to that address, appearing to come from the sender's own
+/// relay port.
+fn relay_port(
+    state: &mut State,
+    shared: &Shared,
+    stop: &Arc<AtomicBool>,
+    address: SocketAddr,
+) -> u16 {
+    if let Some(relay) = state.relays.iter().find(|relay| relay.address == address) {
+        return relay.socket.local_addr().unwrap().port();
+    }
+
+    let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").expect("Failed to bind relay"));
+    socket.set_read_timeout(Some(POLL_INTERVAL)).unwrap();
+    let port = socket.local_addr().unwrap().port();
+
+    state.relays.push(Relay {
+        address,
+        socket: socket.clone(),
+    });
+
+    let shared = shared.clone();
+    let stop = stop.clone();
+    thread::spawn(move || forward(socket, address, shared, stop));
+
+    port
+}
+
+fn forward(socket: Arc<UdpSocket>, target: SocketAddr, state: Shared, stop: Arc<AtomicBool>) {
+    let mut buf = [0u8; 1500];
+
+    while !stop.load(Ordering::Relaxed) {
+        let Ok((len, from)) = socket.recv_from(&mut buf) else {
+            continue;
+        };
+
+        let mut state = state.lock().unwrap();
+        if state.lost_welcomes > 0 && &buf[..len] == b"noray-welcome" {
+            state.lost_welcomes -= 1;
+            continue;
+        }
+
+        let sender = state
+            .relays
+            .iter()
+            .find(|relay| relay.address == from)
+            .map(|relay| relay.socket.clone());
+        drop(state);
+
+        // Like noray, drop packets from addresses without a relay.
+        if let Some(sender) = sender {
+            let _ = sender.send_to(&buf[..len], target);
+        }
+    }
+}
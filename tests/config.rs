@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use bevy_noray::config::{Cli, Command, ConfigError, ConfigOverrides, load_config};
+use bevy_noray::network::NorayConfig;
+use clap::Parser;
+
+fn env_from(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = vars
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    move |name| vars.get(name).cloned()
+}
+
+#[test]
+fn parses_host_and_join_subcommands() {
+    let cli = Cli::try_parse_from(["bevy-noray", "host", "--players", "3"]).unwrap();
+    assert_eq!(cli.command, Some(Command::Host { players: 3 }));
+
+    let cli = Cli::try_parse_from([
+        "bevy-noray",
+        "join",
+        "abc123",
+        "--noray-host",
+        "noray.example.com",
+        "--tcp-port",
+        "9000",
+    ])
+    .unwrap();
+    assert_eq!(
+        cli.command,
+        Some(Command::Join {
+            host_oid: "abc123".into()
+        })
+    );
+
+    let mut config = NorayConfig::default();
+    cli.apply(&mut config);
+    assert_eq!(config.host, "noray.example.com");
+    assert_eq!(config.tcp_port, 9000);
+    assert_eq!(config.udp_port, NorayConfig::default().udp_port);
+
+    let cli = Cli::try_parse_from(["bevy-noray"]).unwrap();
+    assert_eq!(cli.command, None);
+
+    assert!(Cli::try_parse_from(["bevy-noray", "host", "--players", "0"]).is_err());
+}
+
+#[test]
+fn environment_overrides_config_file() {
+    let path = std::env::temp_dir().join(format!("bevy-noray-{}.toml", std::process::id()));
+    std::fs::write(
+        &path,
+        "host = \"10.0.0.1\"\ntcp_port = 7000\ndirect_connect = true\npunch_timeout_ms = 500\n",
+    )
+    .unwrap();
+
+    let config = load_config(Some(&path), env_from(&[("NORAY_TCP_PORT", "7100")])).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(config.host, "10.0.0.1");
+    assert_eq!(config.tcp_port, 7100);
+    assert_eq!(config.udp_port, NorayConfig::default().udp_port);
+    assert!(config.direct_connect);
+    assert_eq!(config.punch_timeout, Duration::from_millis(500));
+}
+
+#[test]
+fn rejects_bad_config_and_environment() {
+    assert!(matches!(
+        ConfigOverrides::from_toml("tcp_prot = 1"),
+        Err(ConfigError::Parse(_))
+    ));
+
+    match ConfigOverrides::from_env(env_from(&[("NORAY_UDP_PORT", "eighty")])) {
+        Err(ConfigError::InvalidEnv { name, value }) => {
+            assert_eq!(name, "NORAY_UDP_PORT");
+            assert_eq!(value, "eighty");
+        }
+        other => panic!("expected InvalidEnv, got {:?}", other),
+    }
+
+    assert!(matches!(
+        load_config(Some("/nonexistent/noray.toml".as_ref()), |_| None),
+        Err(ConfigError::Io { .. })
+    ));
+}
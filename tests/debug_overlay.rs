@@ -0,0 +1,60 @@
+use bevy::prelude::*;
+use bevy_noray::SimulationTick;
+use bevy_noray::debug_overlay::{NetworkDebugOverlay, NetworkDebugPlugin};
+use bevy_noray::sync::{NetworkStats, NetworkTime, RemotePlayerData};
+
+fn app() -> App {
+    let mut remote_data = RemotePlayerData::default();
+    remote_data
+        .players
+        .insert("remote".to_string(), (10.0, 20.0, 0.0, 0.0, false));
+    remote_data.last_seen.insert("remote".to_string(), 0.0);
+
+    let mut app = App::new();
+    app.insert_resource(ButtonInput::<KeyCode>::default())
+        .insert_resource(remote_data)
+        .insert_resource(NetworkTime::new(60.0, true))
+        .init_resource::<NetworkStats>()
+        .init_resource::<SimulationTick>()
+        .add_plugins(NetworkDebugPlugin::default());
+    app
+}
+
+fn toggle(app: &mut App) {
+    let mut keyboard = app.world_mut().resource_mut::<ButtonInput<KeyCode>>();
+    keyboard.release(KeyCode::F3);
+    keyboard.clear();
+    keyboard.press(KeyCode::F3);
+    app.update();
+}
+
+fn sprite_positions(app: &mut App) -> Vec<Vec2> {
+    app.world_mut()
+        .query_filtered::<&Transform, With<Sprite>>()
+        .iter(app.world())
+        .map(|transform| transform.translation.truncate())
+        .collect()
+}
+
+#[test]
+fn overlay_lists_peers_and_shows_ghosts_only_while_visible() {
+    let mut app = app();
+    app.update();
+    assert!(sprite_positions(&mut app).is_empty());
+
+    toggle(&mut app);
+    assert!(app.world().resource::<NetworkDebugOverlay>().visible);
+    assert_eq!(sprite_positions(&mut app), [Vec2::new(10.0, 20.0)]);
+
+    let text = app
+        .world_mut()
+        .query::<&Text>()
+        .single(app.world())
+        .sections[0]
+        .value
+        .clone();
+    assert!(text.contains("remote | - | - | - | - | -"), "{}", text);
+
+    toggle(&mut app);
+    assert!(sprite_positions(&mut app).is_empty());
+}
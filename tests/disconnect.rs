@@ -0,0 +1,116 @@
+mod common;
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_noray::game::player::{IsJumping, Player, SPAWN_POSITION, Velocity};
+use bevy_noray::local_player_data::LocalPlayerMarker;
+use bevy_noray::sync::{DisconnectReason, PeerDisconnected, PeerTimeout, RemotePlayerData};
+use bevy_noray::{NorayPlugin, PlayerRegistrationInfo};
+use common::{MockNoray, in_game, oid, update_until};
+
+#[derive(Resource, Default)]
+struct Disconnected(Vec<PeerDisconnected>);
+
+fn record_disconnects(
+    mut events: EventReader<PeerDisconnected>,
+    mut disconnected: ResMut<Disconnected>,
+) {
+    disconnected.0.extend(events.read().cloned());
+}
+
+fn app(noray: NorayPlugin) -> App {
+    let mut app = common::app(noray);
+    app.init_resource::<Disconnected>()
+        .add_systems(Update, record_disconnects);
+    app
+}
+
+/// A host and a joiner in game, with the joiner's player shown on the host.
+/// Keep the server alive.
+fn connected_pair(timeout: Duration) -> (MockNoray, App, App) {
+    let noray = MockNoray::start();
+    let mut host = app(NorayPlugin::host(noray.config(), 2));
+    host.insert_resource(PeerTimeout(timeout));
+
+    update_until(&mut [&mut host], |apps| {
+        apps[0]
+            .world()
+            .contains_resource::<PlayerRegistrationInfo>()
+    });
+    let mut joiner = app(NorayPlugin::join(noray.config(), oid(&host)));
+    update_until(&mut [&mut host, &mut joiner], |apps| {
+        in_game(apps[0]) && in_game(apps[1])
+    });
+
+    let joiner_oid = oid(&joiner);
+    joiner.world_mut().spawn((
+        Player {
+            oid: joiner_oid.clone(),
+            is_local: true,
+        },
+        Velocity::default(),
+        IsJumping(false),
+        Transform::from_translation(SPAWN_POSITION),
+        LocalPlayerMarker,
+    ));
+    update_until(&mut [&mut host, &mut joiner], |apps| {
+        has_player(apps[0], &joiner_oid)
+    });
+    (noray, host, joiner)
+}
+
+fn has_player(app: &mut App, oid: &str) -> bool {
+    app.world_mut()
+        .query::<&Player>()
+        .iter(app.world())
+        .any(|player| player.oid == oid)
+}
+
+fn disconnects(app: &App) -> Vec<(String, DisconnectReason)> {
+    app.world()
+        .resource::<Disconnected>()
+        .0
+        .iter()
+        .map(|event| (event.oid.clone(), event.reason))
+        .collect()
+}
+
+#[test]
+fn silent_peers_are_despawned_after_the_timeout() {
+    let (_noray, mut host, joiner) = connected_pair(Duration::from_millis(300));
+    let joiner_oid = oid(&joiner);
+
+    // The joiner stops updating, so it sends no more state.
+    update_until(&mut [&mut host], |apps| {
+        !has_player(apps[0], &joiner_oid) && !disconnects(apps[0]).is_empty()
+    });
+
+    assert_eq!(
+        disconnects(&host),
+        vec![(joiner_oid.clone(), DisconnectReason::TimedOut)]
+    );
+    let remote = host.world().resource::<RemotePlayerData>();
+    assert!(!remote.players.contains_key(&joiner_oid));
+    assert!(!remote.departed.contains(&joiner_oid));
+}
+
+#[test]
+fn peers_that_leave_are_despawned_right_away() {
+    let (_noray, mut host, mut joiner) = connected_pair(Duration::from_secs(60));
+    let joiner_oid = oid(&joiner);
+
+    joiner.world_mut().send_event(AppExit::Success);
+    joiner.update();
+    update_until(&mut [&mut host], |apps| {
+        !has_player(apps[0], &joiner_oid) && !disconnects(apps[0]).is_empty()
+    });
+
+    assert_eq!(
+        disconnects(&host),
+        vec![(joiner_oid.clone(), DisconnectReason::Left)]
+    );
+    let remote = host.world().resource::<RemotePlayerData>();
+    assert!(!remote.players.contains_key(&joiner_oid));
+    assert!(remote.departed.contains(&joiner_oid));
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use std::time::Duration;
+
+use bevy_noray::network::handshake::{self, HandshakeProgress, NoraySession};
+use bevy_noray::network::{ConnectionPath, GameState, NorayConfig, NorayError};
+use common::{MockNoray, TIMEOUT, session_pair};
+
+fn state(session: &NoraySession) -> GameState {
+    GameState {
+        oid: session.registration.oid.clone(),
+        frame: 1,
+        x: 0.0,
+        y: 0.0,
+        vx: 0.0,
+        vy: 0.0,
+        is_jumping: false,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn host_and_joiner_exchange_state_through_noray() {
+    let noray = MockNoray::start();
+    let config = noray.config();
+
+    let (host_progress, host_updates) = crossbeam_channel::unbounded();
+    let host = tokio::spawn(handshake::host(config.clone(), 2, host_progress));
+
+    let host_oid = loop {
+        match host_updates.recv_timeout(TIMEOUT).unwrap() {
+            HandshakeProgress::Registered(registration) => break registration.oid,
+            _ => continue,
+        }
+    };
+
+    let (join_progress, _join_updates) = crossbeam_channel::unbounded();
+    let joiner = handshake::join(config, host_oid, join_progress)
+        .await
+        .unwrap();
+    let host = host.await.unwrap().unwrap();
+
+    assert!(host.registration.oid.starts_with("mock-oid-"));
+    assert!(host.registration.pid.starts_with("mock-pid-"));
+    let joined = host_updates.try_iter().find_map(|update| match update {
+        HandshakeProgress::PeerJoined { peer, .. } => Some(peer),
+        _ => None,
+    });
+    let joined = joined.unwrap();
+    assert_eq!(joined.path, ConnectionPath::Relay);
+    assert_ne!(joined.port, 0);
+
+    joiner.channels.sync_tx.send(state(&joiner)).unwrap();
+
+    let state = host.channels.receiver.recv_timeout(TIMEOUT).unwrap();
+    assert_eq!(state.oid, joiner.registration.oid);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn joining_an_unknown_host_fails() {
+    let noray = MockNoray::start();
+    let (progress, _updates) = crossbeam_channel::unbounded();
+
+    let result = handshake::join(noray.config(), "missing".to_string(), progress).await;
+
+    assert!(matches!(result, Err(NorayError::UnknownOid(oid)) if oid == "missing"));
+}
+
+/// The path each side of a two player session uses to reach the other.
+fn paths(host: &NoraySession, joiner: &NoraySession) -> (ConnectionPath, ConnectionPath) {
+    let path = |session: &NoraySession| {
+        let stats = session.channels.stats.snapshot();
+        assert_eq!(stats.len(), 1);
+        stats.into_values().next().unwrap().path.unwrap()
+    };
+    (path(host), path(joiner))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn joiners_connect_directly_when_punching_through_works() {
+    let noray = MockNoray::start();
+    let config = NorayConfig {
+        direct_connect: true,
+        ..noray.config()
+    };
+
+    let (host, joiner) = session_pair(config).await;
+
+    assert_eq!(
+        paths(&host, &joiner),
+        (ConnectionPath::Direct, ConnectionPath::Direct)
+    );
+    joiner.channels.sync_tx.send(state(&joiner)).unwrap();
+    assert_eq!(
+        host.channels.receiver.recv_timeout(TIMEOUT).unwrap().oid,
+        joiner.registration.oid
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn joiners_fall_back_to_the_relay_when_punching_through_fails() {
+    let noray = MockNoray::without_punch_through();
+    let config = NorayConfig {
+        direct_connect: true,
+        punch_timeout: Duration::from_millis(300),
+        ..noray.config()
+    };
+
+    let (host, joiner) = session_pair(config).await;
+
+    assert_eq!(
+        paths(&host, &joiner),
+        (ConnectionPath::Relay, ConnectionPath::Relay)
+    );
+    joiner.channels.sync_tx.send(state(&joiner)).unwrap();
+    assert_eq!(
+        host.channels.receiver.recv_timeout(TIMEOUT).unwrap().oid,
+        joiner.registration.oid
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_host_session_welcomes_joiners_whose_welcome_got_lost() {
+    let noray = MockNoray::losing_first_welcome();
+    let config = NorayConfig {
+        direct_connect: false,
+        ..noray.config()
+    };
+
+    // The host stops waiting at the first hello, so only its session can
+    // answer the joiner's next one.
+    let (host, joiner) = session_pair(config).await;
+
+    assert_eq!(
+        paths(&host, &joiner),
+        (ConnectionPath::Relay, ConnectionPath::Relay)
+    );
+    joiner.channels.sync_tx.send(state(&joiner)).unwrap();
+    assert_eq!(
+        host.channels.receiver.recv_timeout(TIMEOUT).unwrap().oid,
+        joiner.registration.oid
+    );
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+use bevy_noray::local_player_data::LocalPlayerMarker;
+use bevy_noray::network::GameState;
+use bevy_noray::network::handshake::{self, NoraySession};
+use bevy_noray::sync::RemotePlayerData;
+use bevy_noray::{NorayPlugin, NorayState};
+use common::{MockNoray, in_game, oid, state, update_until};
+
+fn joined_state(joiner: &NoraySession) -> GameState {
+    GameState {
+        oid: joiner.registration.oid.clone(),
+        frame: 1,
+        x: 30.0,
+        y: 40.0,
+        vx: 0.0,
+        vy: 0.0,
+        is_jumping: false,
+    }
+}
+
+#[test]
+fn dedicated_host_runs_under_minimal_plugins_without_a_local_player() {
+    let noray = MockNoray::start();
+    let config = noray.config();
+
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, StatesPlugin))
+        .add_plugins(NorayPlugin::dedicated_host(config.clone(), 1));
+
+    update_until(&mut [&mut app], |apps| {
+        state(apps[0]) == NorayState::WaitingForPeers
+    });
+    let host_oid = oid(&app);
+
+    let joiner = std::thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (progress, _updates) = crossbeam_channel::unbounded();
+        runtime
+            .block_on(handshake::join(config, host_oid, progress))
+            .unwrap()
+    });
+
+    update_until(&mut [&mut app], |apps| in_game(apps[0]));
+    let joiner = joiner.join().unwrap();
+    joiner.channels.sync_tx.send(joined_state(&joiner)).unwrap();
+
+    let joiner_oid = joiner.registration.oid.clone();
+    update_until(&mut [&mut app], |apps| {
+        apps[0]
+            .world()
+            .resource::<RemotePlayerData>()
+            .players
+            .contains_key(&joiner_oid)
+    });
+
+    let local_players = app
+        .world_mut()
+        .query_filtered::<(), With<LocalPlayerMarker>>()
+        .iter(app.world())
+        .count();
+    assert_eq!(local_players, 0);
+}
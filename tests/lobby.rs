@@ -0,0 +1,170 @@
+mod common;
+
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::input::{ButtonState, InputPlugin};
+use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+use bevy_noray::lobby::{Lobby, LobbyButton, LobbyPlugin, LobbyScreen};
+use bevy_noray::network::NorayConfig;
+use bevy_noray::plugin::SyncChannel;
+use bevy_noray::sync::SessionStats;
+use bevy_noray::{
+    NetworkMessages, NetworkingState, NorayPlugin, NorayState, PlayerRegistrationInfo,
+};
+use common::{MockNoray, state, update_until};
+
+fn lobby_app(config: NorayConfig) -> App {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, StatesPlugin, InputPlugin))
+        .add_plugins((NorayPlugin::lobby(config), LobbyPlugin));
+    app.update();
+    app
+}
+
+fn press(app: &mut App, target: LobbyButton) {
+    let mut buttons = app.world_mut().query::<(&LobbyButton, &mut Interaction)>();
+    let mut button = buttons
+        .iter_mut(app.world_mut())
+        .find(|(button, _)| **button == target)
+        .unwrap_or_else(|| panic!("no {:?} button", target));
+    *button.1 = Interaction::Pressed;
+    app.update();
+}
+
+fn type_text(app: &mut App, text: &str) {
+    for c in text.chars() {
+        app.world_mut().send_event(KeyboardInput {
+            key_code: KeyCode::KeyA,
+            logical_key: Key::Character(c.to_string().as_str().into()),
+            state: ButtonState::Pressed,
+            window: Entity::PLACEHOLDER,
+        });
+    }
+    app.update();
+}
+
+#[test]
+fn host_and_joiner_meet_in_the_lobby_and_start_together() {
+    let noray = MockNoray::start();
+    let mut host = lobby_app(noray.config());
+    let mut joiner = lobby_app(noray.config());
+    assert_eq!(state(&host), NorayState::Lobby);
+
+    press(&mut host, LobbyButton::ShowHost);
+    press(&mut host, LobbyButton::MorePlayers);
+    press(&mut host, LobbyButton::FewerPlayers);
+    press(&mut host, LobbyButton::FewerPlayers);
+    assert_eq!(host.world().resource::<Lobby>().players, 2);
+    press(&mut host, LobbyButton::Host);
+
+    update_until(&mut [&mut host], |apps| {
+        state(apps[0]) == NorayState::WaitingForPeers
+            && apps[0]
+                .world()
+                .contains_resource::<PlayerRegistrationInfo>()
+    });
+    let host_oid = host
+        .world()
+        .resource::<PlayerRegistrationInfo>()
+        .oid
+        .clone();
+
+    press(&mut joiner, LobbyButton::ShowJoin);
+    type_text(&mut joiner, &host_oid);
+    assert_eq!(joiner.world().resource::<Lobby>().screen, LobbyScreen::Join);
+    assert_eq!(joiner.world().resource::<Lobby>().host_oid, host_oid);
+    press(&mut joiner, LobbyButton::Join);
+
+    update_until(&mut [&mut host, &mut joiner], |apps| {
+        state(apps[0]) == NorayState::Ready && state(apps[1]) == NorayState::Ready
+    });
+    assert_eq!(host.world().resource::<NetworkingState>().peers.len(), 1);
+
+    press(&mut host, LobbyButton::Start);
+    update_until(&mut [&mut host, &mut joiner], |apps| {
+        state(apps[0]) == NorayState::InGame && state(apps[1]) == NorayState::InGame
+    });
+
+    let buttons = host
+        .world_mut()
+        .query::<&LobbyButton>()
+        .iter(host.world())
+        .count();
+    assert_eq!(buttons, 0);
+}
+
+#[test]
+fn failed_handshake_returns_to_the_lobby() {
+    let noray = MockNoray::start();
+    let mut app = lobby_app(noray.config());
+
+    press(&mut app, LobbyButton::ShowJoin);
+    type_text(&mut app, "missing");
+    press(&mut app, LobbyButton::Join);
+
+    update_until(&mut [&mut app], |apps| state(apps[0]) == NorayState::Failed);
+    assert!(
+        app.world()
+            .resource::<NetworkingState>()
+            .error_message
+            .contains("missing")
+    );
+
+    press(&mut app, LobbyButton::Back);
+    app.update();
+    assert_eq!(state(&app), NorayState::Lobby);
+    assert_eq!(app.world().resource::<Lobby>().screen, LobbyScreen::Menu);
+    assert!(
+        app.world()
+            .resource::<NetworkingState>()
+            .error_message
+            .is_empty()
+    );
+}
+
+#[test]
+fn returning_to_the_lobby_closes_the_session() {
+    let noray = MockNoray::start();
+    let mut host = lobby_app(noray.config());
+    let mut joiner = lobby_app(noray.config());
+
+    press(&mut host, LobbyButton::ShowHost);
+    press(&mut host, LobbyButton::Host);
+    update_until(&mut [&mut host], |apps| {
+        apps[0]
+            .world()
+            .contains_resource::<PlayerRegistrationInfo>()
+    });
+    let host_oid = host
+        .world()
+        .resource::<PlayerRegistrationInfo>()
+        .oid
+        .clone();
+
+    press(&mut joiner, LobbyButton::ShowJoin);
+    type_text(&mut joiner, &host_oid);
+    press(&mut joiner, LobbyButton::Join);
+    update_until(&mut [&mut host, &mut joiner], |apps| {
+        state(apps[1]) == NorayState::Ready
+    });
+    assert!(joiner.world().contains_resource::<NetworkMessages>());
+
+    joiner
+        .world_mut()
+        .resource_mut::<NextState<NorayState>>()
+        .set(NorayState::Lobby);
+    joiner.update();
+    joiner.update();
+
+    assert_eq!(state(&joiner), NorayState::Lobby);
+    assert!(!joiner.world().contains_resource::<NetworkMessages>());
+    assert!(!joiner.world().contains_resource::<SyncChannel>());
+    assert!(!joiner.world().contains_resource::<SessionStats>());
+    assert!(
+        joiner
+            .world()
+            .resource::<NetworkingState>()
+            .peers
+            .is_empty()
+    );
+}
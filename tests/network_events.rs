@@ -0,0 +1,85 @@
+mod common;
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_noray::network::Channel;
+use bevy_noray::sync::{AppNetworkEventExt, NetworkEvent, NetworkTarget};
+use bevy_noray::{NorayPlugin, PlayerRegistrationInfo};
+use common::{MockNoray, in_game, oid, update_until};
+use serde::{Deserialize, Serialize};
+
+#[derive(Event, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Hit {
+    attacker: String,
+    victim: String,
+}
+
+#[derive(Resource, Default)]
+struct Hits(Vec<Hit>);
+
+fn record_hits(mut hits: EventReader<Hit>, mut received: ResMut<Hits>) {
+    received.0.extend(hits.read().cloned());
+}
+
+fn app(noray: NorayPlugin) -> App {
+    let mut app = common::app(noray);
+    app.add_network_event::<Hit>(Channel::RELIABLE)
+        .init_resource::<Hits>()
+        .add_systems(Update, record_hits);
+    app
+}
+
+fn hits(app: &App) -> usize {
+    app.world().resource::<Hits>().0.len()
+}
+
+fn send(app: &mut App, target: NetworkTarget, hit: &Hit) {
+    app.world_mut()
+        .send_event(NetworkEvent::new(target, hit.clone()));
+}
+
+#[test]
+fn events_reach_only_their_targets() {
+    let noray = MockNoray::start();
+    let mut host = app(NorayPlugin::host(noray.config(), 3));
+    update_until(&mut [&mut host], |apps| {
+        apps[0]
+            .world()
+            .contains_resource::<PlayerRegistrationInfo>()
+    });
+    let host_oid = oid(&host);
+    let mut alice = app(NorayPlugin::join(noray.config(), host_oid.clone()));
+    let mut bob = app(NorayPlugin::join(noray.config(), host_oid.clone()));
+    update_until(&mut [&mut host, &mut alice, &mut bob], |apps| {
+        apps.iter().all(|app| in_game(app))
+    });
+    let hit = Hit {
+        attacker: oid(&alice),
+        victim: oid(&bob),
+    };
+
+    send(&mut alice, NetworkTarget::Host, &hit);
+    update_until(&mut [&mut host, &mut alice, &mut bob], |apps| {
+        hits(apps[0]) == 1
+    });
+    assert_eq!(host.world().resource::<Hits>().0, vec![hit.clone()]);
+
+    send(&mut host, NetworkTarget::Peer(oid(&bob)), &hit);
+    update_until(&mut [&mut host, &mut alice, &mut bob], |apps| {
+        hits(apps[2]) == 1
+    });
+
+    send(&mut alice, NetworkTarget::All, &hit);
+    update_until(&mut [&mut host, &mut alice, &mut bob], |apps| {
+        hits(apps[0]) == 2 && hits(apps[1]) == 1 && hits(apps[2]) == 2
+    });
+
+    for _ in 0..20 {
+        for app in [&mut host, &mut alice, &mut bob] {
+            app.update();
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!((hits(&host), hits(&alice), hits(&bob)), (2, 1, 2));
+}
@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use bevy_noray::network::{ConnectionStats, PeerStats};
+use bevy_noray::sync::NetworkStats;
+
+fn peer() -> SocketAddr {
+    "127.0.0.1:9000".parse().unwrap()
+}
+
+#[test]
+fn jitter_follows_round_trip_variation_and_late_pongs_are_ignored() {
+    let stats = ConnectionStats::default();
+
+    stats.ping_sent(peer(), 1);
+    stats.pong_received(peer(), 1, Duration::from_millis(40));
+    stats.ping_sent(peer(), 2);
+    stats.pong_received(peer(), 2, Duration::from_millis(56));
+    // No ping was sent at 3.
+    stats.pong_received(peer(), 3, Duration::from_millis(500));
+
+    let peer_stats = stats.snapshot()[&peer()];
+    assert_eq!(peer_stats.pings_answered, 2);
+    assert_eq!(peer_stats.rtt, Duration::from_millis(56));
+    assert_eq!(peer_stats.jitter, Duration::from_millis(1));
+    assert_eq!(peer_stats.loss(), 0.0);
+}
+
+#[test]
+fn bandwidth_is_measured_over_whole_intervals() {
+    let mut stats = NetworkStats::default();
+    let totals = |bytes_sent| {
+        HashMap::from([(
+            peer(),
+            PeerStats {
+                bytes_sent,
+                ..Default::default()
+            },
+        )])
+    };
+
+    assert!(stats.update(Duration::ZERO, totals(0)));
+    assert!(!stats.update(Duration::from_millis(500), totals(300)));
+    assert!(stats.update(Duration::from_secs(2), totals(1000)));
+
+    let peer_stats = stats.peer(peer()).unwrap();
+    assert_eq!(peer_stats.totals.bytes_sent, 1000);
+    assert_eq!(peer_stats.bytes_sent_per_second, 500.0);
+}
@@ -0,0 +1,229 @@
+mod common;
+
+use std::net::UdpSocket;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use bevy_noray::network::{
+    AuthoritativeState, Channel, ChannelMessage, ConnectionPath, GameState, HostSession,
+    InputCommand, NetMessage, PING_INTERVAL, PROTOCOL_VERSION, PeerInfo, PlayerInput, SessionEvent,
+};
+use bevy_noray::sync::{NetworkId, ReplicationMessage, ReplicationPacket};
+use common::{MockNoray, TIMEOUT, session_pair};
+
+fn game_state(oid: &str, frame: u32) -> GameState {
+    GameState {
+        oid: oid.to_string(),
+        frame,
+        x: 1.0,
+        y: 2.0,
+        vx: 3.0,
+        vy: 4.0,
+        is_jumping: true,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn session_delivers_state_and_reliable_messages_both_ways() {
+    let noray = MockNoray::start();
+    let (host, joiner) = session_pair(noray.config()).await;
+    let oid = joiner.registration.oid.clone();
+    let (host, joiner) = (host.channels, joiner.channels);
+
+    joiner.sync_tx.send(game_state(&oid, 1)).unwrap();
+    assert_eq!(host.receiver.recv_timeout(TIMEOUT).unwrap().oid, oid);
+
+    host.sync_tx.send(game_state("host", 1)).unwrap();
+    assert_eq!(joiner.receiver.recv_timeout(TIMEOUT).unwrap().oid, "host");
+
+    for payload in [b"first".to_vec(), b"second".to_vec()] {
+        host.message_tx
+            .send(ChannelMessage {
+                channel: Channel::RELIABLE,
+                payload,
+            })
+            .unwrap();
+    }
+
+    let first = joiner.message_rx.recv_timeout(TIMEOUT).unwrap();
+    let second = joiner.message_rx.recv_timeout(TIMEOUT).unwrap();
+    assert_eq!(first.payload, b"first");
+    assert_eq!(second.payload, b"second");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn session_carries_inputs_to_the_host_and_authoritative_states_back() {
+    let noray = MockNoray::start();
+    let (host, joiner) = session_pair(noray.config()).await;
+    let oid = joiner.registration.oid.clone();
+    let (host, joiner) = (host.channels, joiner.channels);
+
+    // Inputs claiming another player are dropped by the host.
+    for (oid, sequence) in [("someone-else", 3), (oid.as_str(), 4)] {
+        joiner
+            .input_tx
+            .send(InputCommand {
+                oid: oid.to_string(),
+                sequence,
+                input: PlayerInput {
+                    direction: 1,
+                    jump: true,
+                    dt: 0.016,
+                },
+            })
+            .unwrap();
+    }
+
+    let command = host.inputs.recv_timeout(TIMEOUT).unwrap();
+    assert_eq!(command.oid, oid);
+    assert_eq!(command.sequence, 4);
+    assert_eq!(command.input.direction, 1);
+    assert!(command.input.jump);
+
+    host.authoritative_tx
+        .send(AuthoritativeState {
+            last_input: 4,
+            state: game_state("joiner", 4),
+        })
+        .unwrap();
+
+    let authoritative = joiner.authoritative_rx.recv_timeout(TIMEOUT).unwrap();
+    assert_eq!(authoritative.last_input, 4);
+    // Also delivered as plain state, for rendering remote players.
+    assert_eq!(joiner.receiver.recv_timeout(TIMEOUT).unwrap().oid, "joiner");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn session_pings_measure_rtt_offset_and_peer_tick() {
+    let noray = MockNoray::start();
+    let (host, joiner) = session_pair(noray.config()).await;
+    let (host, joiner) = (host.channels, joiner.channels);
+    host.local_tick.store(42, Ordering::Relaxed);
+
+    let sample = joiner.clock.recv_timeout(TIMEOUT).unwrap();
+    assert_eq!(sample.remote_tick, 42);
+    assert!(sample.rtt < Duration::from_millis(500));
+    // Both session clocks started moments apart on the same machine.
+    assert!(sample.offset.abs() < 0.5);
+
+    let stats = joiner.stats.snapshot()[&sample.peer];
+    assert_eq!(stats.pings_answered, 1);
+    assert_eq!(stats.rtt, sample.rtt);
+    assert!(stats.packets_sent >= 1 && stats.packets_received >= 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn host_drops_traffic_claiming_another_player() {
+    let noray = MockNoray::start();
+    let (host, joiner) = session_pair(noray.config()).await;
+    let oid = joiner.registration.oid.clone();
+    let (host, joiner) = (host.channels, joiner.channels);
+
+    joiner.sync_tx.send(game_state("someone-else", 1)).unwrap();
+    joiner.sync_tx.send(game_state(&oid, 2)).unwrap();
+    let state = host.receiver.recv_timeout(TIMEOUT).unwrap();
+    assert_eq!((state.oid.as_str(), state.frame), (oid.as_str(), 2));
+
+    let despawn = |from: &str| {
+        let packet = ReplicationPacket {
+            from: from.to_string(),
+            message: ReplicationMessage::Despawn(NetworkId::player(from)),
+        };
+        ChannelMessage {
+            channel: Channel::REPLICATION,
+            payload: bincode::serialize(&packet).unwrap(),
+        }
+    };
+    let control = ChannelMessage {
+        channel: Channel::CONTROL,
+        payload: Vec::new(),
+    };
+    for message in [despawn("someone-else"), control, despawn(&oid)] {
+        joiner.message_tx.send(message).unwrap();
+    }
+
+    let received = host.message_rx.recv_timeout(TIMEOUT).unwrap();
+    let packet: ReplicationPacket = bincode::deserialize(&received.payload).unwrap();
+    assert_eq!(packet.from, oid);
+    assert!(
+        host.message_rx
+            .recv_timeout(Duration::from_millis(200))
+            .is_err()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn host_stops_sending_to_joiners_that_left() {
+    let noray = MockNoray::start();
+    let (host, joiner) = session_pair(noray.config()).await;
+    let oid = joiner.registration.oid.clone();
+    let (host, joiner) = (host.channels, joiner.channels);
+
+    joiner.disconnect.send(&oid).unwrap();
+    let peer = loop {
+        if let SessionEvent::PeerLeft { peer, .. } = host.events.recv_timeout(TIMEOUT).unwrap() {
+            break peer;
+        }
+    };
+    let sent = host.stats.snapshot()[&peer].packets_sent;
+
+    host.sync_tx.send(game_state("host", 1)).unwrap();
+    host.authoritative_tx
+        .send(AuthoritativeState {
+            last_input: 1,
+            state: game_state(&oid, 1),
+        })
+        .unwrap();
+    host.message_tx
+        .send(ChannelMessage {
+            channel: Channel::RELIABLE,
+            payload: vec![1],
+        })
+        .unwrap();
+    std::thread::sleep(PING_INTERVAL * 3);
+
+    assert_eq!(host.stats.snapshot()[&peer].packets_sent, sent);
+}
+
+#[test]
+fn sessions_report_peers_on_another_protocol_version_once() {
+    let session_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let info = PeerInfo {
+        host: "127.0.0.1".to_string(),
+        port: peer.local_addr().unwrap().port(),
+        path: ConnectionPath::Direct,
+        oid: None,
+    };
+    let session = HostSession::new(session_socket.try_clone().unwrap(), &[info])
+        .unwrap()
+        .start();
+
+    let mut packet = NetMessage::Disconnect {
+        oid: "player".to_string(),
+    }
+    .encode()
+    .unwrap();
+    packet[2] = PROTOCOL_VERSION + 1;
+    for _ in 0..3 {
+        peer.send_to(&packet, session_socket.local_addr().unwrap())
+            .unwrap();
+    }
+
+    match session.events.recv_timeout(TIMEOUT).unwrap() {
+        SessionEvent::ProtocolMismatch {
+            peer: from,
+            version,
+        } => {
+            assert_eq!(from, peer.local_addr().unwrap());
+            assert_eq!(version, PROTOCOL_VERSION + 1);
+        }
+        other => panic!("Unexpected event {:?}", other),
+    }
+    assert!(
+        session
+            .events
+            .recv_timeout(Duration::from_millis(200))
+            .is_err()
+    );
+}
@@ -0,0 +1,276 @@
+mod common;
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_noray::game::player::{MOVE_SPEED, Player};
+use bevy_noray::game::{IsJumping, LocalInputLatch, Velocity, latch_local_input};
+use bevy_noray::local_player_data::LocalPlayerMarker;
+use bevy_noray::network::handshake;
+use bevy_noray::network::{AuthoritativeState, GameState, InputCommand, PlayerInput};
+use bevy_noray::sync::authoritative::{
+    reconcile_local_player, send_local_input, simulate_remote_inputs,
+};
+use bevy_noray::sync::{
+    AuthorityChannels, PeerConnected, PendingInputs, RemotePlayerData, SimulatedPlayer,
+};
+use bevy_noray::{NorayPlugin, PlayerRegistrationInfo, SyncMode};
+use common::{MockNoray, in_game, oid, update_until};
+use crossbeam_channel::{Receiver, Sender};
+
+const DT: Duration = Duration::from_millis(20);
+
+struct Joiner {
+    app: App,
+    sent: Receiver<InputCommand>,
+    host: Sender<AuthoritativeState>,
+}
+
+fn joiner() -> Joiner {
+    let (input_tx, sent) = crossbeam_channel::unbounded();
+    let (host, authoritative_rx) = crossbeam_channel::unbounded();
+    let (authoritative_tx, _) = crossbeam_channel::unbounded();
+    let (_, inputs) = crossbeam_channel::unbounded();
+
+    let mut app = App::new();
+    app.insert_resource(Time::<()>::default())
+        .insert_resource(ButtonInput::<KeyCode>::default())
+        .insert_resource(PlayerRegistrationInfo {
+            oid: "joiner".to_string(),
+            pid: "pid".to_string(),
+        })
+        .insert_resource(AuthorityChannels {
+            input_tx,
+            inputs,
+            authoritative_tx,
+            authoritative_rx,
+        })
+        .init_resource::<LocalInputLatch>()
+        .init_resource::<PendingInputs>()
+        .add_systems(
+            Update,
+            (latch_local_input, reconcile_local_player, send_local_input).chain(),
+        );
+
+    app.world_mut().spawn((
+        LocalPlayerMarker,
+        Transform::default(),
+        Velocity::default(),
+        IsJumping(false),
+    ));
+
+    Joiner { app, sent, host }
+}
+
+fn step(app: &mut App) {
+    app.world_mut().resource_mut::<Time>().advance_by(DT);
+    app.update();
+}
+
+fn local_x(app: &mut App) -> f32 {
+    app.world_mut()
+        .query_filtered::<&Transform, With<LocalPlayerMarker>>()
+        .single(app.world())
+        .translation
+        .x
+}
+
+fn host_state(x: f32, last_input: u32) -> AuthoritativeState {
+    AuthoritativeState {
+        last_input,
+        state: GameState {
+            oid: "joiner".to_string(),
+            frame: last_input,
+            x,
+            y: 25.0,
+            vx: MOVE_SPEED,
+            vy: 0.0,
+            is_jumping: false,
+        },
+    }
+}
+
+#[test]
+fn inputs_are_numbered_and_sent_redundantly() {
+    let mut joiner = joiner();
+
+    for _ in 0..4 {
+        step(&mut joiner.app);
+    }
+
+    let sequences: Vec<u32> = joiner.sent.try_iter().map(|c| c.sequence).collect();
+    assert_eq!(sequences, [1, 1, 2, 1, 2, 3, 2, 3, 4]);
+}
+
+#[test]
+fn unacknowledged_inputs_are_replayed_on_the_host_state() {
+    let mut joiner = joiner();
+    joiner
+        .app
+        .world_mut()
+        .resource_mut::<ButtonInput<KeyCode>>()
+        .press(KeyCode::KeyD);
+
+    // Inputs 1 to 3 are sent; the host has only applied the first one.
+    for _ in 0..3 {
+        step(&mut joiner.app);
+    }
+    joiner.host.send(host_state(100.0, 1)).unwrap();
+    step(&mut joiner.app);
+
+    // Inputs 2 and 3 are replayed on top of the host's x.
+    let expected = 100.0 + 2.0 * MOVE_SPEED * DT.as_secs_f32();
+    assert!((local_x(&mut joiner.app) - expected).abs() < 1e-3);
+}
+
+#[test]
+fn stale_host_states_are_ignored() {
+    let mut joiner = joiner();
+
+    for _ in 0..3 {
+        step(&mut joiner.app);
+    }
+    joiner.host.send(host_state(50.0, 3)).unwrap();
+    step(&mut joiner.app);
+    joiner.host.send(host_state(-50.0, 2)).unwrap();
+    step(&mut joiner.app);
+
+    assert_eq!(local_x(&mut joiner.app), 50.0);
+}
+
+#[test]
+fn the_host_simulates_one_input_per_player_and_tick() {
+    let (input_tx, _) = crossbeam_channel::unbounded();
+    let (authoritative_tx, _) = crossbeam_channel::unbounded();
+    let (_, authoritative_rx) = crossbeam_channel::unbounded();
+    let (joiner, inputs) = crossbeam_channel::unbounded();
+
+    let mut host = App::new();
+    host.insert_resource(Time::<()>::default())
+        .insert_resource(AuthorityChannels {
+            input_tx,
+            inputs,
+            authoritative_tx,
+            authoritative_rx,
+        })
+        .init_resource::<RemotePlayerData>()
+        .add_event::<PeerConnected>()
+        .add_systems(Update, simulate_remote_inputs);
+
+    // A flood of long steps arrives at once.
+    for sequence in 1..=20 {
+        joiner
+            .send(InputCommand {
+                oid: "joiner".to_string(),
+                sequence,
+                input: PlayerInput {
+                    direction: 1,
+                    jump: false,
+                    dt: 1.0,
+                },
+            })
+            .unwrap();
+    }
+
+    let simulated = |host: &mut App| {
+        host.world_mut()
+            .query_filtered::<&Transform, With<SimulatedPlayer>>()
+            .single(host.world())
+            .translation
+            .x
+    };
+
+    step(&mut host);
+    let start = simulated(&mut host);
+    step(&mut host);
+    step(&mut host);
+
+    // Each tick moves by one tick's worth of a single input.
+    let expected = start + 2.0 * MOVE_SPEED * DT.as_secs_f32();
+    assert!((simulated(&mut host) - expected).abs() < 1e-3);
+    let last_input = host
+        .world_mut()
+        .query::<&SimulatedPlayer>()
+        .single(host.world())
+        .last_input;
+    assert!(last_input < 20);
+}
+
+#[test]
+fn the_host_ignores_state_forged_by_a_joiner() {
+    let noray = MockNoray::start();
+    let config = noray.config();
+    let mut host = common::app(
+        NorayPlugin::dedicated_host(config.clone(), 1).with_sync_mode(SyncMode::HostAuthoritative),
+    );
+
+    update_until(&mut [&mut host], |apps| {
+        apps[0]
+            .world()
+            .contains_resource::<PlayerRegistrationInfo>()
+    });
+    let host_oid = oid(&host);
+    let joiner = std::thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (progress, _updates) = crossbeam_channel::unbounded();
+        runtime
+            .block_on(handshake::join(config, host_oid, progress))
+            .unwrap()
+    });
+    update_until(&mut [&mut host], |apps| in_game(apps[0]));
+    let joiner = joiner.join().unwrap();
+    let joiner_oid = joiner.registration.oid.clone();
+
+    let forged = GameState {
+        oid: joiner_oid.clone(),
+        frame: 1,
+        x: 500.0,
+        y: 0.0,
+        vx: 0.0,
+        vy: 0.0,
+        is_jumping: false,
+    };
+    joiner.channels.sync_tx.send(forged.clone()).unwrap();
+    joiner
+        .channels
+        .authoritative_tx
+        .send(AuthoritativeState {
+            last_input: 1,
+            state: forged,
+        })
+        .unwrap();
+    joiner
+        .channels
+        .input_tx
+        .send(InputCommand {
+            oid: joiner_oid.clone(),
+            sequence: 1,
+            input: PlayerInput::default(),
+        })
+        .unwrap();
+
+    let joiner_players = |app: &mut App| {
+        app.world_mut()
+            .query::<(&Player, &Transform)>()
+            .iter(app.world())
+            .filter(|(player, _)| player.oid == joiner_oid)
+            .map(|(_, transform)| transform.translation.x)
+            .collect::<Vec<_>>()
+    };
+    update_until(&mut [&mut host], |apps| !joiner_players(apps[0]).is_empty());
+    for _ in 0..20 {
+        host.update();
+        std::thread::sleep(Duration::from_millis(5));
+    }
+
+    let xs = joiner_players(&mut host);
+    assert_eq!(xs.len(), 1);
+    assert_ne!(xs[0], 500.0);
+    assert!(
+        !host
+            .world()
+            .resource::<RemotePlayerData>()
+            .players
+            .contains_key(&joiner_oid)
+    );
+}
@@ -0,0 +1,84 @@
+use std::time::{Duration, Instant};
+
+use bevy_noray::network::{Channel, ChannelPacket, NetMessage, ReliableLink};
+
+fn packet(bytes: &[u8]) -> ChannelPacket {
+    match NetMessage::decode(bytes).unwrap() {
+        NetMessage::Channel(packet) => packet,
+        other => panic!("expected a channel packet, got {:?}", other),
+    }
+}
+
+#[test]
+fn unacknowledged_messages_are_given_up_eventually() {
+    let mut link = ReliableLink::default();
+    link.encode(Channel::RELIABLE, b"hello".to_vec()).unwrap();
+    let now = Instant::now();
+
+    assert_eq!(link.resend_due(now + Duration::from_secs(1)).len(), 1);
+    assert_eq!(link.given_up(), 0);
+
+    // Only the skip past it is sent from then on.
+    for later in [60, 120] {
+        let due = link.resend_due(now + Duration::from_secs(later));
+        assert_eq!(due.len(), 1);
+        assert!(matches!(
+            packet(&due[0]),
+            ChannelPacket::Skip { sequence: 1, .. }
+        ));
+    }
+    assert_eq!(link.given_up(), 1);
+}
+
+#[test]
+fn messages_past_a_gap_are_buffered_up_to_a_limit() {
+    let mut sender = ReliableLink::default();
+    let mut receiver = ReliableLink::default();
+
+    let missing = sender.encode(Channel::RELIABLE, vec![0]).unwrap();
+    let mut acked = 0;
+    for index in 1..=300u16 {
+        let bytes = sender
+            .encode(Channel::RELIABLE, index.to_le_bytes().to_vec())
+            .unwrap();
+        let (ack, delivered) = receiver.receive(packet(&bytes));
+        assert!(delivered.is_empty());
+        acked += usize::from(ack.is_some());
+    }
+    // Dropped messages are not acked, so the sender keeps resending them.
+    assert!(acked < 300);
+
+    let (_, delivered) = receiver.receive(packet(&missing));
+    assert_eq!(delivered.len(), acked + 1);
+}
+
+#[test]
+fn receivers_skip_past_messages_the_sender_gave_up_on() {
+    let mut sender = ReliableLink::default();
+    let mut receiver = ReliableLink::default();
+
+    let _lost = sender.encode(Channel::RELIABLE, vec![0]).unwrap();
+    for index in 1..=3u8 {
+        let bytes = sender.encode(Channel::RELIABLE, vec![index]).unwrap();
+        let (ack, delivered) = receiver.receive(packet(&bytes));
+        assert!(delivered.is_empty());
+        sender.receive(packet(&ack.unwrap()));
+    }
+
+    let later = Instant::now() + Duration::from_secs(60);
+    let due = sender.resend_due(later);
+    assert_eq!(sender.given_up(), 1);
+    assert_eq!(due.len(), 1);
+
+    let (ack, delivered) = receiver.receive(packet(&due[0]));
+    let payloads: Vec<_> = delivered.into_iter().map(|m| m.payload).collect();
+    assert_eq!(payloads, vec![vec![1], vec![2], vec![3]]);
+
+    // Once acked, the skip is not sent again.
+    sender.receive(packet(&ack.unwrap()));
+    assert!(sender.resend_due(later + Duration::from_secs(1)).is_empty());
+
+    let bytes = sender.encode(Channel::RELIABLE, vec![4]).unwrap();
+    let (_, delivered) = receiver.receive(packet(&bytes));
+    assert_eq!(delivered[0].payload, vec![4]);
+}
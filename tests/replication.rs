@@ -0,0 +1,119 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_noray::game::player::{IsJumping, Player, SPAWN_POSITION, Velocity};
+use bevy_noray::local_player_data::LocalPlayerMarker;
+use bevy_noray::sync::{NetworkEntityMap, NetworkId, Replicated};
+use bevy_noray::{NorayPlugin, PlayerRegistrationInfo};
+use common::{Health, MockNoray, app, in_game, update_until};
+
+fn remote_health(app: &mut App, oid: &str) -> Option<Health> {
+    app.world_mut()
+        .query::<(&Player, &Health)>()
+        .iter(app.world())
+        .find(|(player, _)| player.oid == oid)
+        .map(|(_, health)| *health)
+}
+
+/// A host and a joiner in game, with the host's OID. Keep the server alive.
+fn connected_pair() -> (MockNoray, App, App, String) {
+    let noray = MockNoray::start();
+    let mut host = app(NorayPlugin::host(noray.config(), 2));
+
+    update_until(&mut [&mut host], |apps| {
+        apps[0]
+            .world()
+            .contains_resource::<PlayerRegistrationInfo>()
+    });
+    let host_oid = host
+        .world()
+        .resource::<PlayerRegistrationInfo>()
+        .oid
+        .clone();
+    let mut joiner = app(NorayPlugin::join(noray.config(), host_oid.clone()));
+
+    update_until(&mut [&mut host, &mut joiner], |apps| {
+        in_game(apps[0]) && in_game(apps[1])
+    });
+    (noray, host, joiner, host_oid)
+}
+
+fn networked_health(app: &mut App) -> Vec<(NetworkId, Health)> {
+    app.world_mut()
+        .query_filtered::<(&NetworkId, &Health), Without<Player>>()
+        .iter(app.world())
+        .map(|(id, health)| (id.clone(), *health))
+        .collect()
+}
+
+#[test]
+fn changed_components_reach_the_remote_copy() {
+    let (_noray, mut host, mut joiner, host_oid) = connected_pair();
+
+    let local = host
+        .world_mut()
+        .spawn((
+            Player {
+                oid: host_oid.clone(),
+                is_local: true,
+            },
+            Velocity::default(),
+            IsJumping(false),
+            Transform::from_translation(SPAWN_POSITION),
+            LocalPlayerMarker,
+            Replicated,
+            Health(100),
+        ))
+        .id();
+
+    update_until(&mut [&mut host, &mut joiner], |apps| {
+        remote_health(apps[1], &host_oid) == Some(Health(100))
+    });
+
+    host.world_mut().get_mut::<Health>(local).unwrap().0 = 42;
+    update_until(&mut [&mut host, &mut joiner], |apps| {
+        remote_health(apps[1], &host_oid) == Some(Health(42))
+    });
+}
+
+#[test]
+fn spawned_entities_appear_and_despawn_remotely() {
+    let (_noray, mut host, mut joiner, host_oid) = connected_pair();
+
+    let potion = host.world_mut().spawn((Replicated, Health(25))).id();
+    update_until(&mut [&mut host, &mut joiner], |apps| {
+        networked_health(apps[1]).len() == 1
+    });
+
+    let id = host.world().get::<NetworkId>(potion).unwrap().clone();
+    assert_eq!(id.owner, host_oid);
+    assert!(!id.is_player());
+    assert_eq!(
+        networked_health(&mut joiner),
+        vec![(id.clone(), Health(25))]
+    );
+    assert!(
+        joiner
+            .world()
+            .resource::<NetworkEntityMap>()
+            .entity(&id)
+            .is_some()
+    );
+
+    host.world_mut().get_mut::<Health>(potion).unwrap().0 = 5;
+    update_until(&mut [&mut host, &mut joiner], |apps| {
+        networked_health(apps[1]) == vec![(id.clone(), Health(5))]
+    });
+
+    host.world_mut().despawn(potion);
+    update_until(&mut [&mut host, &mut joiner], |apps| {
+        networked_health(apps[1]).is_empty()
+    });
+    assert!(
+        joiner
+            .world()
+            .resource::<NetworkEntityMap>()
+            .entity(&id)
+            .is_none()
+    );
+}
@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_noray::game::player::{MOVE_SPEED, Player, SPAWN_POSITION};
+use bevy_noray::game::{IsJumping, LocalInputLatch, Velocity};
+use bevy_noray::local_player_data::LocalPlayerMarker;
+use bevy_noray::network::{PlayerInput, RollbackInput};
+use bevy_noray::sync::rollback::advance_rollback;
+use bevy_noray::sync::{
+    PeerConnected, RemotePlayerData, RollbackChannels, RollbackConfig, RollbackSession,
+};
+use bevy_noray::{PlayerRegistrationInfo, SimulationTick};
+use crossbeam_channel::{Receiver, Sender};
+
+const DT: Duration = Duration::from_millis(20);
+
+struct Peer {
+    app: App,
+    sent: Receiver<RollbackInput>,
+    remote: Sender<RollbackInput>,
+}
+
+fn peer() -> Peer {
+    let (tx, sent) = crossbeam_channel::unbounded();
+    let (remote, rx) = crossbeam_channel::unbounded();
+
+    let mut app = App::new();
+    app.insert_resource(Time::<()>::default())
+        .insert_resource(PlayerRegistrationInfo {
+            oid: "local".to_string(),
+            pid: "pid".to_string(),
+        })
+        .insert_resource(RollbackChannels { tx, rx })
+        .init_resource::<RollbackConfig>()
+        .init_resource::<RollbackSession>()
+        .init_resource::<LocalInputLatch>()
+        .init_resource::<RemotePlayerData>()
+        .init_resource::<SimulationTick>()
+        .add_event::<PeerConnected>()
+        .add_systems(Update, advance_rollback);
+
+    app.world_mut().spawn((
+        Player {
+            oid: "local".to_string(),
+            is_local: true,
+        },
+        LocalPlayerMarker,
+        Transform::from_translation(SPAWN_POSITION),
+        Velocity::default(),
+        IsJumping(false),
+    ));
+
+    Peer { app, sent, remote }
+}
+
+fn step(app: &mut App) {
+    app.world_mut().resource_mut::<Time>().advance_by(DT);
+    app.update();
+    app.world_mut().resource_mut::<SimulationTick>().0 += 1;
+}
+
+fn remote_inputs(first_frame: u32, direction: i8, count: usize) -> RollbackInput {
+    RollbackInput {
+        oid: "remote".to_string(),
+        first_frame,
+        inputs: vec![
+            PlayerInput {
+                direction,
+                jump: false,
+                dt: DT.as_secs_f32(),
+            };
+            count
+        ],
+    }
+}
+
+fn remote_x(app: &mut App) -> f32 {
+    let mut query = app.world_mut().query::<(&Player, &Transform)>();
+    query
+        .iter(app.world())
+        .find(|(player, _)| player.oid == "remote")
+        .map(|(_, transform)| transform.translation.x)
+        .expect("remote player was not spawned")
+}
+
+#[test]
+fn late_remote_input_is_applied_by_rolling_back() {
+    let mut peer = peer();
+
+    // The remote player appears standing still, then is predicted to keep
+    // standing still for frames 1 to 4.
+    peer.remote.send(remote_inputs(0, 0, 1)).unwrap();
+    for _ in 0..5 {
+        step(&mut peer.app);
+    }
+    assert_eq!(remote_x(&mut peer.app), SPAWN_POSITION.x);
+
+    // It was actually walking right since frame 1.
+    peer.remote.send(remote_inputs(1, 1, 4)).unwrap();
+    step(&mut peer.app);
+
+    // Frames 1 to 4 are re-simulated, and frame 5 repeats the last input.
+    let expected = SPAWN_POSITION.x + 5.0 * MOVE_SPEED * DT.as_secs_f32();
+    assert!((remote_x(&mut peer.app) - expected).abs() < 1e-3);
+    assert_eq!(peer.app.world().resource::<RollbackSession>().frame(), 6);
+}
+
+#[test]
+fn local_inputs_are_sent_with_recent_frames() {
+    let mut peer = peer();
+
+    for _ in 0..10 {
+        step(&mut peer.app);
+    }
+
+    let last = peer.sent.try_iter().last().unwrap();
+    let redundancy = RollbackConfig::default().input_redundancy;
+    assert_eq!(last.oid, "local");
+    assert_eq!(last.first_frame, 10 - redundancy);
+    assert_eq!(last.inputs.len(), redundancy as usize);
+}
+
+#[test]
+fn frames_are_numbered_by_the_shared_tick() {
+    let mut peer = peer();
+    // Joined late: the host is already at tick 100.
+    peer.app.world_mut().resource_mut::<SimulationTick>().0 = 100;
+
+    peer.remote.send(remote_inputs(100, 0, 1)).unwrap();
+    step(&mut peer.app);
+    peer.remote.send(remote_inputs(101, 1, 3)).unwrap();
+    for _ in 0..3 {
+        step(&mut peer.app);
+    }
+
+    let expected = SPAWN_POSITION.x + 3.0 * MOVE_SPEED * DT.as_secs_f32();
+    assert!((remote_x(&mut peer.app) - expected).abs() < 1e-3);
+    assert_eq!(peer.app.world().resource::<RollbackSession>().frame(), 104);
+    assert_eq!(peer.sent.try_iter().last().unwrap().first_frame, 104 - 8);
+
+    // A tick that falls back does not simulate frames twice.
+    peer.app.world_mut().resource_mut::<SimulationTick>().0 = 101;
+    step(&mut peer.app);
+    assert!((remote_x(&mut peer.app) - expected).abs() < 1e-3);
+    assert_eq!(peer.app.world().resource::<RollbackSession>().frame(), 104);
+}
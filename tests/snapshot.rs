@@ -0,0 +1,132 @@
+use std::time::{Duration, Instant};
+
+use bevy_noray::network::{
+    GameState, IDLE_KEEPALIVE, NetMessage, SLOT_TIMEOUT, SlotsExhausted, SnapshotAck, SnapshotLink,
+    StateDelta,
+};
+
+const OID: &str = "0123456789abcdef0123456789abcdef";
+
+fn state(frame: u32, x: f32) -> GameState {
+    GameState {
+        oid: OID.to_string(),
+        frame,
+        x,
+        y: 2.0,
+        vx: 3.0,
+        vy: 0.0,
+        is_jumping: false,
+    }
+}
+
+fn ack(snapshot: &StateDelta) -> SnapshotAck {
+    SnapshotAck {
+        slot: snapshot.slot,
+        sequence: snapshot.sequence,
+    }
+}
+
+fn wire_size(message: NetMessage) -> usize {
+    message.encode().unwrap().len()
+}
+
+#[test]
+fn deltas_against_the_acknowledged_baseline_round_trip() {
+    let mut sender = SnapshotLink::default();
+    let mut receiver = SnapshotLink::default();
+    let now = Instant::now();
+
+    let full = sender.encode(&state(1, 1.0), now).unwrap().unwrap();
+    assert!(full.is_full());
+    assert_eq!(receiver.decode(full.clone()).unwrap().x, 1.0);
+
+    // Not acknowledged yet, so the next one is full too.
+    assert!(
+        sender
+            .encode(&state(2, 1.5), now)
+            .unwrap()
+            .unwrap()
+            .is_full()
+    );
+
+    sender.acknowledge(ack(&full));
+    let delta = sender.encode(&state(3, 4.0), now).unwrap().unwrap();
+    assert_eq!(delta.baseline, Some(full.sequence));
+    assert_eq!(delta.oid, None);
+    assert_eq!(delta.values, vec![4.0]);
+
+    let decoded = receiver.decode(delta.clone()).unwrap();
+    assert_eq!(decoded.oid, OID);
+    assert_eq!((decoded.frame, decoded.x, decoded.y), (3, 4.0, 2.0));
+
+    assert!(
+        wire_size(NetMessage::StateDelta(delta)) * 3
+            < wire_size(NetMessage::GameState(state(3, 4.0)))
+    );
+}
+
+#[test]
+fn idle_players_are_only_sent_as_keepalives() {
+    let mut sender = SnapshotLink::default();
+    let now = Instant::now();
+
+    let full = sender.encode(&state(1, 1.0), now).unwrap().unwrap();
+    sender.acknowledge(ack(&full));
+
+    assert_eq!(sender.encode(&state(2, 1.0), now), Ok(None));
+    assert_eq!(
+        sender.encode(&state(3, 1.0), now + Duration::from_millis(500)),
+        Ok(None)
+    );
+
+    let keepalive = sender
+        .encode(&state(4, 1.0), now + IDLE_KEEPALIVE)
+        .unwrap()
+        .unwrap();
+    assert_eq!(keepalive.values, Vec::<f32>::new());
+    assert_eq!(keepalive.frame, 3);
+}
+
+#[test]
+fn snapshots_with_an_unknown_baseline_are_dropped() {
+    let mut sender = SnapshotLink::default();
+    let mut receiver = SnapshotLink::default();
+    let now = Instant::now();
+
+    let full = sender.encode(&state(1, 1.0), now).unwrap().unwrap();
+    sender.acknowledge(ack(&full));
+    let delta = sender.encode(&state(2, 5.0), now).unwrap().unwrap();
+
+    assert!(receiver.decode(delta).is_none());
+}
+
+#[test]
+fn slots_are_freed_and_reused() {
+    let mut sender = SnapshotLink::default();
+    let mut receiver = SnapshotLink::default();
+    let now = Instant::now();
+    let player = |index: u32| GameState {
+        oid: format!("player-{index}"),
+        ..state(1, 1.0)
+    };
+
+    for index in 0..256 {
+        let full = sender.encode(&player(index), now).unwrap().unwrap();
+        assert_eq!(u32::from(full.slot), index);
+        receiver.decode(full).unwrap();
+    }
+    assert_eq!(sender.encode(&player(256), now), Err(SlotsExhausted));
+
+    // A player that left gives its slot to the next one.
+    sender.remove("player-7");
+    let full = sender.encode(&player(256), now).unwrap().unwrap();
+    assert_eq!(full.slot, 7);
+    assert_eq!(receiver.decode(full).unwrap().oid, "player-256");
+
+    // So does one that was not sent for a while.
+    let later = now + SLOT_TIMEOUT;
+    sender.encode(&player(3), later).unwrap();
+    assert_eq!(sender.encode(&player(257), later).unwrap().unwrap().slot, 0);
+    assert_eq!(sender.encode(&player(258), later).unwrap().unwrap().slot, 1);
+    assert_eq!(sender.encode(&player(3), later).unwrap().unwrap().slot, 3);
+}